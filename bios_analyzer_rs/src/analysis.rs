@@ -1,6 +1,9 @@
 //! Analysis functions for BIOS structures
 
+use crate::confidence::*;
+use crate::memclk::*;
 use crate::patterns::*;
+use crate::spd::*;
 use crate::structures::*;
 use byteorder::{LittleEndian, ReadBytesExt};
 use colored::Colorize;
@@ -20,6 +23,45 @@ pub fn find_pattern(data: &[u8], pattern: &[u8]) -> Vec<usize> {
     results
 }
 
+/// Little-endian readers that return None past the end of the buffer
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+pub fn read_u24(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 3)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], 0]))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// `phrase` occurs in `text` (case-insensitive) as whole words, not inside a longer word
+pub fn contains_word(text: &str, phrase: &str) -> bool {
+    let (text, phrase) = (text.to_ascii_uppercase(), phrase.to_ascii_uppercase());
+    text.match_indices(&phrase).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + phrase.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric()) && !after.is_some_and(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// Format a 16-byte EFI GUID in registry order
+pub fn format_guid(bytes: &[u8]) -> String {
+    format!(
+        "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        bytes[3], bytes[2], bytes[1], bytes[0],
+        bytes[5], bytes[4], bytes[7], bytes[6],
+        bytes[8], bytes[9], bytes[10], bytes[11],
+        bytes[12], bytes[13], bytes[14], bytes[15]
+    )
+}
+
 /// Analyze UEFI Firmware Volumes
 pub fn analyze_uefi_volumes(data: &[u8], report: &mut BiosReport) {
     println!("\n{}", "Analyzing UEFI volumes...".dimmed());
@@ -36,13 +78,7 @@ pub fn analyze_uefi_volumes(data: &[u8], report: &mut BiosReport) {
                 // Read GUID (16 bytes)
                 let mut guid = [0u8; 16];
                 if cursor.read_exact(&mut guid).is_ok() {
-                    let guid_str = format!(
-                        "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
-                        guid[3], guid[2], guid[1], guid[0],
-                        guid[5], guid[4], guid[7], guid[6],
-                        guid[8], guid[9], guid[10], guid[11],
-                        guid[12], guid[13], guid[14], guid[15]
-                    );
+                    let guid_str = format_guid(&guid);
 
                     // Read volume length
                     cursor.set_position(0x20);
@@ -68,39 +104,26 @@ pub fn analyze_spd_structures(data: &[u8], report: &mut BiosReport) {
     let spd_offsets = find_pattern(data, SPD_SIGNATURE);
     
     for offset in spd_offsets {
-        if offset + 32 <= data.len() {
-            let spd_data = &data[offset..offset + 32];
+        if offset + 128 <= data.len() {
+            let spd_data = &data[offset..offset + 128];
             let vendor = format!("{:02x}{:02x}{:02x}{:02x}", 
                 spd_data[4], spd_data[5], spd_data[6], spd_data[7]);
-            let tck = spd_data[0x0C];
-            let locked = tck == 0x0A;
-            
+
             let spd = SpdStructure {
                 offset: offset as u64,
                 vendor,
-                tck,
-                locked,
-                raw: hex::encode(&spd_data[..16]),
+                raw: hex::encode(spd_data),
+                decoded: decode_spd(data, offset),
             };
             
-            // Add patch candidate for locked SPD
-            if locked {
-                report.patches.push(PatchCandidate {
-                    offset: (offset + 0x0C) as u64,
-                    original: vec![0x0A],
-                    patched: vec![0x02],
-                    description: "SPD tCK unlock".to_string(),
-                    effect: "Enable frequencies above 6400 MT/s".to_string(),
-                    risk: "low".to_string(),
-                });
-            }
-            
             report.spd_structures.push(spd);
         }
     }
-    println!("  Found {} SPD structures ({} locked)", 
+    report.spd_groups = group_spds(&report.spd_structures);
+    println!("  Found {} SPD structures ({} with bad CRC), {} vendor/part group(s)", 
         report.spd_structures.len(),
-        report.spd_structures.iter().filter(|s| s.locked).count());
+        report.spd_structures.iter().filter(|s| s.decoded.as_ref().is_some_and(|d| !d.crc_valid)).count(),
+        report.spd_groups.len());
 }
 
 /// Analyze frequency tables
@@ -108,7 +131,9 @@ pub fn analyze_frequency_tables(data: &[u8], report: &mut BiosReport) {
     println!("{}", "Analyzing frequency tables...".dimmed());
     
     // Pattern 0x51 sequence
-    for offset in find_pattern(data, FREQ_PATTERN_51) {
+    let table_offsets = find_pattern(data, FREQ_PATTERN_51);
+    let exposed = if table_offsets.is_empty() { Vec::new() } else { exposed_memclk_codes(data) };
+    for offset in table_offsets {
         if offset + 48 <= data.len() {
             let mut values = Vec::new();
             let mut cursor = Cursor::new(&data[offset..offset + 48]);
@@ -116,24 +141,22 @@ pub fn analyze_frequency_tables(data: &[u8], report: &mut BiosReport) {
                 if val == 0xFFFF { break; }
                 values.push(val);
             }
+            let codes: Vec<MemClkCode> = values.iter()
+                .map(|&code| MemClkCode {
+                    code,
+                    mhz: memclk_mhz(code),
+                    data_rate: memclk_mhz(code).map(|mhz| mhz * 2),
+                    exposed: exposed.contains(&code),
+                    note: memclk_note(code).to_string(),
+                })
+                .collect();
+            let hidden = codes.iter().filter(|c| !c.exposed).map(|c| c.code).collect();
             report.frequency_tables.push(FrequencyTable {
                 offset: offset as u64,
                 values,
                 table_type: "Memory Clock (0x51+)".to_string(),
-            });
-        }
-    }
-
-    // Pattern 0x59 sequence - add patch candidate
-    for offset in find_pattern(data, FREQ_PATTERN_59) {
-        if offset + 32 <= data.len() {
-            report.patches.push(PatchCandidate {
-                offset: offset as u64,
-                original: vec![0x59, 0x00],
-                patched: vec![0x5F, 0x00],
-                description: "Frequency remap".to_string(),
-                effect: "3200MHz selection -> ~7000 MT/s".to_string(),
-                risk: "low".to_string(),
+                codes,
+                hidden,
             });
         }
     }
@@ -168,19 +191,8 @@ pub fn analyze_power_management(data: &[u8], report: &mut BiosReport) {
                             milliwatts: mw,
                             watts: mw / 1000,
                             description: desc.to_string(),
+                            backed: false,
                         });
-                        
-                        // Add patch candidate for 15W -> 25W
-                        if mw == 15000 {
-                            report.patches.push(PatchCandidate {
-                                offset: offset as u64,
-                                original: 15000u32.to_le_bytes().to_vec(),
-                                patched: 25000u32.to_le_bytes().to_vec(),
-                                description: "Power limit increase".to_string(),
-                                effect: "15W -> 25W TDP".to_string(),
-                                risk: "high".to_string(),
-                            });
-                        }
                     }
                 }
             }
@@ -286,8 +298,8 @@ pub fn analyze_guids(data: &[u8], report: &mut BiosReport) {
     println!("  Found {} known GUIDs", report.guids.len());
 }
 
-/// Analyze numeric tables (potential GPU clocks, voltages)
-pub fn analyze_numeric_tables(data: &[u8], report: &mut BiosReport) {
+/// Analyze numeric tables (potential GPU clocks, voltages); data regions only unless scoring allows all
+pub fn analyze_numeric_tables(data: &[u8], scoring: &ScoringContext, report: &mut BiosReport) {
     println!("{}", "Analyzing numeric tables...".dimmed());
     
     // Look for GPU frequency patterns (200-1800 MHz range)
@@ -308,13 +320,14 @@ pub fn analyze_numeric_tables(data: &[u8], report: &mut BiosReport) {
             }
         }
         
-        if valid && vals.len() == 8 {
+        if valid && vals.len() == 8 && scoring.allows(i) {
             let unique: std::collections::HashSet<_> = vals.iter().collect();
             if unique.len() >= 4 {
                 report.numeric_tables.push(NumericTable {
                     offset: i as u64,
                     values: vals,
                     table_type: "Potential GPU Freq".to_string(),
+                    region: scoring.regions.kind_at(i),
                 });
             }
         }
@@ -361,13 +374,5 @@ pub fn analyze_ec(data: &[u8], report: &mut BiosReport) {
         }
     }
     
-    // Look for "Jupiter" (Steam Deck codename)
-    for offset in find_pattern(data, b"Jupiter") {
-        report.ec_info.push(EcInfo {
-            offset: offset as u64,
-            description: "Jupiter (Steam Deck)".to_string(),
-        });
-    }
-    
     println!("  Found {} EC references", report.ec_info.len());
 }
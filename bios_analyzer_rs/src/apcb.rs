@@ -0,0 +1,159 @@
+//! APCB (AMD PSP Customization Block) parser - groups, entries and tokens
+
+use crate::analysis::*;
+use serde::Serialize;
+
+const APCB_SIGNATURE: &[u8] = b"APCB";
+/// Checksum byte; makes the whole block sum to zero
+pub const APCB_CHECKSUM: usize = 16;
+const GROUP_HEADER_SIZE: usize = 16;
+const ENTRY_HEADER_SIZE: usize = 16;
+
+/// Token group ("TOKN")
+pub const APCB_GROUP_TOKEN: u16 = 0x3000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Apcb {
+    pub offset: usize,
+    pub size: usize,
+    pub header_size: usize,
+    pub version: u16,
+    pub checksum_valid: bool,
+    pub groups: Vec<ApcbGroup>,
+    pub tokens: Vec<ApcbToken>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApcbGroup {
+    pub offset: usize,
+    pub signature: String,
+    pub group_id: u16,
+    pub size: usize,
+    pub entries: Vec<ApcbEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApcbEntry {
+    pub offset: usize,
+    pub group_id: u16,
+    pub type_id: u16,
+    pub size: usize,
+    pub instance: u16,
+    pub context_type: u8,
+    pub priority_mask: u8,
+    pub board_mask: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApcbToken {
+    /// Flash offset of the token value
+    pub offset: usize,
+    pub id: u32,
+    pub value: u32,
+    /// Value width in bytes (bool/byte = 1, word = 2, dword = 4)
+    pub width: usize,
+    pub instance: u16,
+}
+
+/// Token value width for a token entry type
+fn token_width(type_id: u16) -> usize {
+    match type_id {
+        2 => 2,
+        4 => 4,
+        _ => 1,
+    }
+}
+
+/// Find and parse all APCB instances in the image
+pub fn parse_apcbs(data: &[u8]) -> Vec<Apcb> {
+    find_pattern(data, APCB_SIGNATURE)
+        .into_iter()
+        .filter_map(|offset| parse_apcb(data, offset))
+        .collect()
+}
+
+fn parse_apcb(data: &[u8], offset: usize) -> Option<Apcb> {
+    let header_size = read_u16(data, offset + 4)? as usize;
+    let version = read_u16(data, offset + 6)?;
+    let size = read_u32(data, offset + 8)? as usize;
+    if !(0x20..=0x100).contains(&header_size) || size < header_size || size > 0x20000
+        || offset + size > data.len() {
+        return None;
+    }
+
+    let body = &data[offset..offset + size];
+    let checksum_valid = body.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)) == 0;
+
+    let mut groups = Vec::new();
+    let mut tokens = Vec::new();
+    let mut pos = offset + header_size;
+    while pos + GROUP_HEADER_SIZE <= offset + size {
+        let signature = &data[pos..pos + 4];
+        if !signature.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+            break;
+        }
+        let group_id = read_u16(data, pos + 4)?;
+        let group_header = read_u16(data, pos + 6)? as usize;
+        let group_size = read_u32(data, pos + 12)? as usize;
+        if group_header < GROUP_HEADER_SIZE || group_size < group_header || pos + group_size > offset + size {
+            break;
+        }
+
+        let entries = parse_entries(data, pos + group_header, pos + group_size);
+        if group_id == APCB_GROUP_TOKEN {
+            for entry in &entries {
+                let width = token_width(entry.type_id);
+                let mut t = entry.offset + ENTRY_HEADER_SIZE;
+                while t + 8 <= entry.offset + entry.size {
+                    let value = read_u32(data, t + 4)?;
+                    let mask = if width == 4 { u32::MAX } else { (1u32 << (width * 8)) - 1 };
+                    tokens.push(ApcbToken {
+                        offset: t + 4,
+                        id: read_u32(data, t)?,
+                        value: value & mask,
+                        width,
+                        instance: entry.instance,
+                    });
+                    t += 8;
+                }
+            }
+        }
+
+        groups.push(ApcbGroup {
+            offset: pos,
+            signature: String::from_utf8_lossy(signature).into_owned(),
+            group_id,
+            size: group_size,
+            entries,
+        });
+        pos += (group_size + 3) & !3;
+    }
+
+    if groups.is_empty() {
+        return None;
+    }
+    Some(Apcb { offset, size, header_size, version, checksum_valid, groups, tokens })
+}
+
+fn parse_entries(data: &[u8], start: usize, end: usize) -> Vec<ApcbEntry> {
+    let mut entries = Vec::new();
+    let mut pos = start;
+    while pos + ENTRY_HEADER_SIZE <= end {
+        let size = read_u16(data, pos + 4).unwrap_or(0) as usize;
+        if size < ENTRY_HEADER_SIZE || pos + size > end {
+            break;
+        }
+        entries.push(ApcbEntry {
+            offset: pos,
+            group_id: read_u16(data, pos).unwrap_or(0),
+            type_id: read_u16(data, pos + 2).unwrap_or(0),
+            size,
+            instance: read_u16(data, pos + 6).unwrap_or(0),
+            context_type: data[pos + 8],
+            priority_mask: data[pos + 11],
+            board_mask: read_u16(data, pos + 14).unwrap_or(0),
+        });
+        pos += (size + 3) & !3;
+    }
+    entries
+}
@@ -0,0 +1,317 @@
+//! Flash-readiness check - brick-risk assessment of a (patched) image
+
+use crate::analysis::*;
+use crate::checksums::*;
+use crate::ffs::*;
+use crate::known_bios::*;
+use crate::mirror::*;
+use crate::patch_engine::*;
+use crate::patch_file::*;
+use crate::psp::*;
+use crate::structures::*;
+use colored::Colorize;
+use memmap2::Mmap;
+use std::fs::File;
+
+/// Raw SPI flash dump (h2offt -g)
+const RAW_FLASH_SIZE: usize = 16 * 1024 * 1024;
+/// DER PKCS#7 SignedData OID 1.2.840.113549.1.7.2
+const PKCS7_SIGNED_DATA_OID: &[u8] = &[0x06, 0x09, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x02];
+
+struct Checks {
+    items: Vec<CheckItem>,
+}
+
+impl Checks {
+    fn add(&mut self, name: &str, status: &str, detail: String) {
+        let tag = match status {
+            "pass" => "PASS".green(),
+            "warn" => "WARN".yellow(),
+            _ => "FAIL".red(),
+        };
+        println!("  [{}] {}: {}", tag, name, detail);
+        self.items.push(CheckItem { name: name.to_string(), status: status.to_string(), detail });
+    }
+}
+
+/// Larger images pass only at the size of a known signed release
+fn check_size(data: &[u8], checks: &mut Checks) -> Result<(), String> {
+    let size = data.len();
+    let db = load_known_bios()?;
+    let capsule = db.releases.iter().find(|r| r.kind == "signed" && r.size == size);
+    if size == RAW_FLASH_SIZE {
+        checks.add("size", "pass", "16 MB raw flash image".to_string());
+    } else if let Some(release) = capsule {
+        checks.add("size", "pass", format!("{} bytes, signed capsule like {}", size, release.version));
+    } else if size > RAW_FLASH_SIZE {
+        checks.add("size", "warn", format!("{} bytes, not the size of any known signed capsule", size));
+    } else {
+        checks.add("size", "fail", format!("{} bytes, smaller than the 16 MB flash", size));
+    }
+    Ok(())
+}
+
+fn check_signature_blob(data: &[u8], checks: &mut Checks) {
+    if data.len() <= RAW_FLASH_SIZE {
+        checks.add("signature", "pass", "raw image, no capsule signature expected".to_string());
+        return;
+    }
+    match find_pattern(data, PKCS7_SIGNED_DATA_OID).last() {
+        Some(offset) => checks.add("signature", "pass", format!("PKCS#7 SignedData @ 0x{:08X}", offset)),
+        None => checks.add("signature", "fail", "capsule without PKCS#7 signature, h2offt will reject it".to_string()),
+    }
+}
+
+fn check_psp(data: &[u8], checks: &mut Checks) -> Vec<PspDirectory> {
+    let dirs = parse_psp_directories(data);
+    match find_efs(data) {
+        None => checks.add("efs", "fail", "Embedded Firmware Structure (0x55AA55AA) not found".to_string()),
+        Some(efs) => {
+            let dangling: Vec<String> = efs.pointers.iter()
+                .filter(|(_, _, offset)| !offset.is_some_and(|o| dirs.iter().any(|d| d.offset == o)))
+                .map(|(name, raw, _)| format!("{}=0x{:08X}", name, raw))
+                .collect();
+            if dangling.is_empty() {
+                checks.add("efs", "pass", format!("@ 0x{:08X}, {} directory pointer(s)", efs.offset, efs.pointers.len()));
+            } else {
+                checks.add("efs", "warn", format!("@ 0x{:08X}, pointers without a directory: {}",
+                    efs.offset, dangling.join(", ")));
+            }
+        }
+    }
+
+    let bad: Vec<String> = dirs.iter()
+        .filter(|d| !d.checksum_valid)
+        .map(|d| format!("{}@0x{:08X}", d.signature, d.offset))
+        .collect();
+    if dirs.is_empty() {
+        checks.add("psp_directories", "fail", "no PSP/BIOS directories found".to_string());
+    } else if bad.is_empty() {
+        checks.add("psp_directories", "pass", format!("{} directories, checksums valid", dirs.len()));
+    } else {
+        checks.add("psp_directories", "fail", format!("bad checksum: {}", bad.join(", ")));
+    }
+    dirs
+}
+
+fn check_ffs(data: &[u8], checks: &mut Checks) {
+    let volumes = parse_firmware_volumes(data);
+    let files = all_files(&volumes);
+    let bad_fv = all_volumes(&volumes).iter().filter(|v| !v.header_checksum_valid).count();
+    let bad: Vec<String> = files.iter()
+        .filter(|f| !file_checksums_valid(data, f))
+        .map(|f| format!("{}@0x{:08X}", f.name.as_deref().unwrap_or(&f.guid), f.offset))
+        .collect();
+    if bad.is_empty() && bad_fv == 0 {
+        checks.add("ffs", "pass", format!("{} volumes, {} files, checksums valid", volumes.len(), files.len()));
+    } else {
+        checks.add("ffs", "fail", format!("{} bad FV header(s), bad file checksums: {}", bad_fv, bad.join(", ")));
+    }
+}
+
+fn check_mirrors(data: &[u8], checks: &mut Checks) {
+    let regions = find_mirror_regions(data);
+    let diverged: usize = regions.iter().map(|r| r.divergences.len()).sum();
+    if regions.is_empty() {
+        checks.add("mirrors", "warn", "no A/B copies found".to_string());
+    } else if diverged == 0 {
+        checks.add("mirrors", "pass", format!("{} mirrored region(s), identical", regions.len()));
+    } else {
+        checks.add("mirrors", "warn", format!("{} divergence(s) between A/B copies", diverged));
+    }
+}
+
+fn check_signed_regions(data: &[u8], stock: &[u8], dirs: &[PspDirectory], checks: &mut Checks) {
+    let diff = match make_patch_file(stock, data, "check") {
+        Ok(diff) => diff,
+        Err(e) => return checks.add("signed_regions", "fail", format!("cannot compare with --stock: {}", e)),
+    };
+    let signed = signed_ranges(dirs);
+    let mut hits = Vec::new();
+    for record in &diff.records {
+        let start = record.offset as usize;
+        let end = start + record.original.len() / 2;
+        for (offset, size, name) in &signed {
+            if start < offset + size && *offset < end {
+                hits.push(format!("0x{:08X} in {}", start, name));
+            }
+        }
+    }
+    if hits.is_empty() {
+        checks.add("signed_regions", "pass", format!("{} modified range(s), none PSP-verified", diff.records.len()));
+    } else {
+        checks.add("signed_regions", "fail", format!("PSP-verified data modified: {}", hits.join(", ")));
+    }
+}
+
+fn check_patch_risk(image: &str, checks: &mut Checks) {
+    let Ok(text) = std::fs::read_to_string(format!("{}.patch.json", image)) else { return };
+    let Ok(summary) = serde_json::from_str::<PatchSummary>(&text) else { return };
+    let worst = summary.applied.iter().map(|p| risk_rank(&p.risk)).max().unwrap_or(0);
+    let risky: Vec<&str> = summary.applied.iter()
+        .filter(|p| risk_rank(&p.risk) > 0)
+        .map(|p| p.description.as_str())
+        .collect();
+    if worst == 0 {
+        checks.add("patch_risk", "pass", format!("{} low-risk patch(es)", summary.applied.len()));
+    } else {
+        checks.add("patch_risk", "warn", format!("{} patch(es) above low risk: {}",
+            RISK_LEVELS.get(worst).unwrap_or(&"unknown"), risky.join(", ")));
+    }
+}
+
+/// Any failure blocks flashing, any warning asks for caution
+fn verdict(items: &[CheckItem]) -> &'static str {
+    if items.iter().any(|i| i.status == "fail") {
+        "DO NOT FLASH"
+    } else if items.iter().any(|i| i.status == "warn") {
+        "FLASH WITH CAUTION"
+    } else {
+        "READY TO FLASH"
+    }
+}
+
+/// `check <image> [--stock stock.fd]`
+pub fn run_check(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer check <image> [--stock stock.fd]";
+    let image = args.first().ok_or(usage)?;
+    let stock_path = match (args.get(1).map(|s| s.as_str()), args.get(2)) {
+        (None, _) => None,
+        (Some("--stock"), Some(path)) => Some(path.clone()),
+        _ => return Err(usage.into()),
+    };
+
+    println!("{}", "═".repeat(80).cyan());
+    println!("{}", " FLASH READINESS CHECK".bold().cyan());
+    println!("{}", "═".repeat(80).cyan());
+
+    let file = File::open(image)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let data = &mmap[..];
+    let mut checks = Checks { items: Vec::new() };
+
+    check_size(data, &mut checks)?;
+    check_signature_blob(data, &mut checks);
+    let dirs = check_psp(data, &mut checks);
+    check_ffs(data, &mut checks);
+    check_mirrors(data, &mut checks);
+    match &stock_path {
+        Some(path) => {
+            let stock = std::fs::read(path)?;
+            check_signed_regions(data, &stock, &dirs, &mut checks);
+        }
+        None => checks.add("signed_regions", "warn", "no --stock image, modifications unknown".to_string()),
+    }
+    check_patch_risk(image, &mut checks);
+
+    let verdict = verdict(&checks.items);
+    println!("\n  {}: {}", "Verdict".bold(), match verdict {
+        "READY TO FLASH" => verdict.green(),
+        "FLASH WITH CAUTION" => verdict.yellow(),
+        _ => verdict.red(),
+    });
+    for item in checks.items.iter().filter(|i| i.status != "pass") {
+        println!("    - {}: {}", item.name, item.detail);
+    }
+
+    let report = CheckReport { image: image.clone(), items: checks.items, verdict: verdict.to_string() };
+    let path = format!("{}.check.json", image);
+    std::fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+    println!("\n{}", format!("Check report saved to {}", path).green());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PSP_DIRECTORY: usize = 0x1000;
+    const EFS: usize = 0x20000;
+    /// PSP firmware entry body, inside the signed range
+    const BODY: usize = 0x2000;
+
+    /// EFS at its flash offset pointing at a $PSP directory with one 0x100-byte entry at BODY
+    fn psp_image() -> Vec<u8> {
+        let mut data = vec![0xFF; EFS + 0x1000];
+        let dir = PSP_DIRECTORY;
+        data[dir..dir + 4].copy_from_slice(b"$PSP");
+        data[dir + 8..dir + 16].copy_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        data[dir + 16..dir + 20].copy_from_slice(&[0x01, 0, 0, 0]);
+        data[dir + 20..dir + 24].copy_from_slice(&0x100u32.to_le_bytes());
+        data[dir + 24..dir + 32].copy_from_slice(&(BODY as u64).to_le_bytes());
+        let checksum = fletcher32(&data[dir + 8..dir + 32]);
+        data[dir + 4..dir + 8].copy_from_slice(&checksum.to_le_bytes());
+        data[EFS..EFS + 4].copy_from_slice(&[0xAA, 0x55, 0xAA, 0x55]);
+        data[EFS + 4..EFS + 0x40].fill(0);
+        data[EFS + 0x14..EFS + 0x18].copy_from_slice(&(PSP_DIRECTORY as u32).to_le_bytes());
+        data
+    }
+
+    fn run(check: impl FnOnce(&mut Checks)) -> Vec<(String, String)> {
+        let mut checks = Checks { items: Vec::new() };
+        check(&mut checks);
+        checks.items.into_iter().map(|i| (i.name, i.status)).collect()
+    }
+
+    fn item(name: &str, status: &str) -> (String, String) {
+        (name.to_string(), status.to_string())
+    }
+
+    fn items(statuses: &[&str]) -> Vec<CheckItem> {
+        statuses.iter()
+            .map(|s| CheckItem { name: "x".to_string(), status: s.to_string(), detail: String::new() })
+            .collect()
+    }
+
+    #[test]
+    fn verdict_is_decided_by_the_worst_check() {
+        assert_eq!(verdict(&items(&["pass", "pass"])), "READY TO FLASH");
+        assert_eq!(verdict(&items(&["pass", "warn"])), "FLASH WITH CAUTION");
+        assert_eq!(verdict(&items(&["warn", "fail", "pass"])), "DO NOT FLASH");
+    }
+
+    #[test]
+    fn check_size_against_flash_and_known_capsules() {
+        let size = |len: usize| run(|c| check_size(&vec![0; len], c).unwrap());
+        assert_eq!(size(RAW_FLASH_SIZE), [item("size", "pass")]);
+        // F7A0133_sign.fd
+        assert_eq!(size(17_778_888), [item("size", "pass")]);
+        assert_eq!(size(RAW_FLASH_SIZE + 1), [item("size", "warn")]);
+        assert_eq!(size(RAW_FLASH_SIZE - 1), [item("size", "fail")]);
+    }
+
+    #[test]
+    fn check_psp_reports_efs_and_directory_checksums() {
+        let data = psp_image();
+        assert_eq!(run(|c| { check_psp(&data, c); }), [item("efs", "pass"), item("psp_directories", "pass")]);
+
+        // No EFS: nothing tells the PSP where its directories are
+        let mut no_efs = data.clone();
+        no_efs[EFS] = 0;
+        assert_eq!(run(|c| { check_psp(&no_efs, c); }), [item("efs", "fail"), item("psp_directories", "pass")]);
+
+        // EFS pointing next to the directory
+        let mut dangling = data.clone();
+        dangling[EFS + 0x14] = 0x10;
+        assert_eq!(run(|c| { check_psp(&dangling, c); })[0], item("efs", "warn"));
+
+        let mut bad_checksum = data.clone();
+        bad_checksum[PSP_DIRECTORY + 4] ^= 1;
+        assert_eq!(run(|c| { check_psp(&bad_checksum, c); })[1], item("psp_directories", "fail"));
+    }
+
+    #[test]
+    fn check_signed_regions_fails_on_psp_verified_changes() {
+        let stock = psp_image();
+        let dirs = parse_psp_directories(&stock);
+        let signed = |at: usize| {
+            let mut data = stock.clone();
+            data[at] ^= 0xFF;
+            run(|c| check_signed_regions(&data, &stock, &dirs, c))
+        };
+        assert_eq!(signed(BODY + 0xFF), [item("signed_regions", "fail")]);
+        assert_eq!(signed(BODY + 0x100), [item("signed_regions", "pass")]);
+        let shorter = run(|c| check_signed_regions(&stock[1..], &stock, &dirs, c));
+        assert_eq!(shorter, [item("signed_regions", "fail")]);
+    }
+}
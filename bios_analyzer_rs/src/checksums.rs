@@ -0,0 +1,276 @@
+//! Checksum fixups - FFS header/file, FV header, CRC32 sections, APCB and SPD CRC after patching
+
+use crate::apcb::*;
+use crate::analysis::*;
+use crate::ffs::*;
+use crate::patterns::*;
+use crate::spd::*;
+use crate::structures::*;
+use colored::Colorize;
+
+/// A checksummed structure, ordered innermost first by `len`
+enum Target<'a> {
+    FileChecksums(&'a FfsFile),
+    Crc32Section(&'a FfsSection),
+    VolumeHeader(&'a FirmwareVolume),
+    ApcbBlock(&'a Apcb),
+    SpdBase(usize),
+}
+
+impl Target<'_> {
+    fn range(&self) -> (usize, usize) {
+        match self {
+            Target::FileChecksums(f) => (f.offset, f.size),
+            Target::Crc32Section(s) => (s.offset, s.size),
+            Target::VolumeHeader(v) => (v.offset, v.header_length),
+            Target::ApcbBlock(a) => (a.offset, a.size),
+            Target::SpdBase(o) => (*o, SPD_BASE_SIZE),
+        }
+    }
+}
+
+fn ffs_header_sum(header: &[u8]) -> u8 {
+    let mut header = header.to_vec();
+    header[FFS_FILE_CHECKSUM] = 0;
+    header[FFS_STATE] = 0;
+    sum8(&header)
+}
+
+fn file_checksum(data: &[u8], file: &FfsFile) -> u8 {
+    if file.attributes & FFS_ATTRIB_CHECKSUM != 0 {
+        sum8(&data[file.data_offset()..file.offset + file.size]).wrapping_neg()
+    } else {
+        FFS_FIXED_CHECKSUM
+    }
+}
+
+pub fn file_checksums_valid(data: &[u8], file: &FfsFile) -> bool {
+    ffs_header_sum(&data[file.offset..file.data_offset()]) == 0
+        && data[file.offset + FFS_FILE_CHECKSUM] == file_checksum(data, file)
+}
+
+fn section_crc(data: &[u8], section: &FfsSection) -> u32 {
+    crc32fast::hash(&data[section.data_offset()..section.offset + section.size])
+}
+
+fn stored_crc(data: &[u8], section: &FfsSection) -> u32 {
+    let at = section.data_offset() - 4;
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn record(fixes: &mut Vec<ChecksumFix>, offset: usize, kind: &str, name: String, old: &[u8], new: &[u8]) {
+    if old != new {
+        fixes.push(ChecksumFix {
+            offset: offset as u64,
+            kind: kind.to_string(),
+            name,
+            old: hex::encode(old),
+            new: hex::encode(new),
+        });
+    }
+}
+
+/// Recompute the checksums of every structure that contains a changed range.
+/// Structures come from `original`; ones whose checksum was already wrong there are left alone.
+pub fn fix_checksums(original: &[u8], patched: &mut [u8], changed: &[(usize, usize)]) -> Vec<ChecksumFix> {
+    let volumes = parse_firmware_volumes(original);
+    let apcbs = parse_apcbs(original);
+    let touches = |start: usize, len: usize| changed.iter().any(|&(o, l)| o < start + len && start < o + l);
+
+    let mut targets: Vec<Target> = Vec::new();
+    for file in all_files(&volumes) {
+        if touches(file.offset, file.size) {
+            targets.push(Target::FileChecksums(file));
+            for section in flatten_sections(&file.sections) {
+                if section.guid.as_deref() == Some(CRC32_SECTION_GUID) && touches(section.offset, section.size) {
+                    targets.push(Target::Crc32Section(section));
+                }
+            }
+        }
+    }
+    for fv in all_volumes(&volumes) {
+        if touches(fv.offset, fv.header_length) {
+            targets.push(Target::VolumeHeader(fv));
+        }
+    }
+    for apcb in &apcbs {
+        if touches(apcb.offset, apcb.size) {
+            targets.push(Target::ApcbBlock(apcb));
+        }
+    }
+    for offset in find_pattern(original, SPD_SIGNATURE) {
+        if touches(offset, SPD_BASE_SIZE) && offset + SPD_BASE_SIZE <= original.len() {
+            targets.push(Target::SpdBase(offset));
+        }
+    }
+    // Inner structures first, so enclosing sums see their updated checksums
+    targets.sort_by_key(|t| t.range().1);
+
+    let mut fixes = Vec::new();
+    for target in &targets {
+        match target {
+            Target::FileChecksums(file) => {
+                if !file_checksums_valid(original, file) {
+                    println!("    {} FFS {} @ 0x{:08X}: checksum invalid in input, left as is",
+                        "warning".yellow(), file.guid, file.offset);
+                    continue;
+                }
+                let name = file.name.clone().unwrap_or_else(|| file.guid.clone());
+                let at = file.offset + FFS_FILE_CHECKSUM;
+                let old = [patched[at]];
+                patched[at] = file_checksum(patched, file);
+                record(&mut fixes, at, "ffs_file", name.clone(), &old, &[patched[at]]);
+
+                let at = file.offset + FFS_HEADER_CHECKSUM;
+                let old = [patched[at]];
+                patched[at] = 0;
+                patched[at] = ffs_header_sum(&patched[file.offset..file.data_offset()]).wrapping_neg();
+                record(&mut fixes, at, "ffs_header", name, &old, &[patched[at]]);
+            }
+            Target::Crc32Section(section) => {
+                if stored_crc(original, section) != section_crc(original, section) {
+                    println!("    {} CRC32 section @ 0x{:08X}: CRC invalid in input, left as is",
+                        "warning".yellow(), section.offset);
+                    continue;
+                }
+                let at = section.data_offset() - 4;
+                let old = patched[at..at + 4].to_vec();
+                let crc = section_crc(patched, section);
+                patched[at..at + 4].copy_from_slice(&crc.to_le_bytes());
+                record(&mut fixes, at, "crc32_section", format!("section @ 0x{:08X}", section.offset),
+                    &old, &crc.to_le_bytes());
+            }
+            Target::VolumeHeader(fv) => {
+                if !fv.header_checksum_valid {
+                    println!("    {} FV @ 0x{:08X}: header checksum invalid in input, left as is",
+                        "warning".yellow(), fv.offset);
+                    continue;
+                }
+                let at = fv.offset + FV_CHECKSUM;
+                let old = patched[at..at + 2].to_vec();
+                patched[at..at + 2].copy_from_slice(&[0, 0]);
+                let sum = sum16(&patched[fv.offset..fv.offset + fv.header_length]).wrapping_neg();
+                patched[at..at + 2].copy_from_slice(&sum.to_le_bytes());
+                record(&mut fixes, at, "fv_header", fv.name.clone().unwrap_or_else(|| fv.guid.clone()),
+                    &old, &sum.to_le_bytes());
+            }
+            Target::ApcbBlock(apcb) => {
+                if !apcb.checksum_valid {
+                    println!("    {} APCB @ 0x{:08X}: checksum invalid in input, left as is",
+                        "warning".yellow(), apcb.offset);
+                    continue;
+                }
+                let at = apcb.offset + APCB_CHECKSUM;
+                let old = [patched[at]];
+                patched[at] = 0;
+                patched[at] = sum8(&patched[apcb.offset..apcb.offset + apcb.size]).wrapping_neg();
+                record(&mut fixes, at, "apcb", format!("APCB @ 0x{:08X}", apcb.offset), &old, &[patched[at]]);
+            }
+            Target::SpdBase(offset) => {
+                // Modified SPD bytes are always re-signed; a CRC that was already wrong is noted in the fix
+                let mut name = format!("SPD @ 0x{:08X}", offset);
+                if !spd_crc_valid(original, *offset) {
+                    println!("    {} {}: CRC invalid in input, re-signed anyway", "warning".yellow(), name);
+                    name.push_str(" (input CRC invalid)");
+                }
+                let at = offset + SPD_CRC_OFFSET;
+                let old = patched[at..at + 2].to_vec();
+                let crc = spd_crc(&patched[*offset..*offset + SPD_BASE_SIZE]);
+                patched[at..at + 2].copy_from_slice(&crc.to_le_bytes());
+                record(&mut fixes, at, "spd_crc", name, &old, &crc.to_le_bytes());
+            }
+        }
+    }
+    fixes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    /// Volume with one file holding a CRC32 section over "123456780"; offsets of the
+    /// stored CRC and of the last data byte
+    fn crc_volume() -> (Vec<u8>, usize, usize) {
+        let fv = volume(&[ffs_file(0x11, &crc32_section(b"123456780"))], 0x1000);
+        let file = 0x48;
+        let data = file + 0x18 + 4 + 24;
+        (fv, data - 4, data + 8)
+    }
+
+    #[test]
+    fn fix_checksums_resums_crc32_section_and_file() {
+        let (fv, crc_at, last) = crc_volume();
+        let mut patched = fv.clone();
+        patched[last] = b'9';
+        let fixes = fix_checksums(&fv, &mut patched, &[(last, 1)]);
+
+        // CRC-32 check value of "123456789"
+        assert_eq!(read_u32(&patched, crc_at), Some(0xCBF4_3926));
+        let volumes = parse_firmware_volumes(&patched);
+        let file = all_files(&volumes)[0];
+        assert_eq!(patched[file.offset + FFS_FILE_CHECKSUM], sum8(&patched[file.data_offset()..file.offset + file.size]).wrapping_neg());
+        assert!(file_checksums_valid(&patched, file));
+        let kinds: Vec<&str> = fixes.iter().map(|f| f.kind.as_str()).collect();
+        assert_eq!(kinds, ["crc32_section", "ffs_file"]);
+        assert_eq!(fixes[0].new, "2639f4cb");
+    }
+
+    #[test]
+    fn fix_checksums_leaves_bad_input_alone() {
+        let (mut fv, crc_at, last) = crc_volume();
+        fv[0x48 + FFS_FILE_CHECKSUM] = fv[0x48 + FFS_FILE_CHECKSUM].wrapping_add(1);
+        fv[crc_at] = fv[crc_at].wrapping_add(1);
+        let mut patched = fv.clone();
+        patched[last] = b'9';
+        assert!(fix_checksums(&fv, &mut patched, &[(last, 1)]).is_empty());
+        assert_eq!(patched[0x48 + FFS_FILE_CHECKSUM], fv[0x48 + FFS_FILE_CHECKSUM]);
+        assert_eq!(patched[crc_at], fv[crc_at]);
+    }
+
+    #[test]
+    fn fix_checksums_resums_volume_header() {
+        let (fv, _, _) = crc_volume();
+        let mut patched = fv.clone();
+        patched[0x36] = 1;
+        let fixes = fix_checksums(&fv, &mut patched, &[(0x36, 1)]);
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].kind, "fv_header");
+        assert_eq!(sum16(&patched[..0x48]), 0);
+        assert!(parse_firmware_volumes(&patched)[0].header_checksum_valid);
+    }
+
+    /// mgcp.txt SPD with the revision byte of the SPDs in the image (0x11), at 0x100
+    fn spd_image() -> Vec<u8> {
+        let mut image = vec![0xFF; 0x100];
+        image.extend(mgcp_spd());
+        image[0x101] = 0x11;
+        image
+    }
+
+    #[test]
+    fn fix_checksums_resigns_spd() {
+        let spd = 0x100;
+        let mut image = spd_image();
+        // CRC-16/XMODEM (Python binascii.crc_hqx(data, 0)) before and after the patch
+        image[spd + SPD_CRC_OFFSET..spd + SPD_BASE_SIZE].copy_from_slice(&0x92B0u16.to_le_bytes());
+        assert!(spd_crc_valid(&image, spd));
+
+        // tCKmin 1.25 -> 1.125 ns
+        let mut patched = image.clone();
+        patched[spd + 18] = 0x09;
+        let fixes = fix_checksums(&image, &mut patched, &[(spd + 18, 1)]);
+        assert_eq!(read_u16(&patched, spd + SPD_CRC_OFFSET), Some(0x5948));
+        assert!(spd_crc_valid(&patched, spd));
+        assert_eq!(fixes.len(), 1);
+
+        // The stored mgcp.txt CRC (60 3F) is already wrong; it is re-signed all the same and the fix says so
+        let stock = spd_image();
+        let mut patched = stock.clone();
+        patched[spd + 18] = 0x09;
+        let fixes = fix_checksums(&stock, &mut patched, &[(spd + 18, 1)]);
+        assert!(spd_crc_valid(&patched, spd));
+        assert_eq!((fixes[0].old.as_str(), fixes[0].new.as_str()), ("603f", "4859"));
+        assert!(fixes[0].name.ends_with("(input CRC invalid)"));
+    }
+}
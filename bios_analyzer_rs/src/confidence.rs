@@ -0,0 +1,240 @@
+//! Confidence scoring for heuristic findings - region, module, nearby strings, plausibility, alignment
+
+use crate::analysis::*;
+use crate::ffs::*;
+use crate::regions::*;
+use serde::Serialize;
+
+/// Score a bare pattern match starts with
+const BASE_SCORE: u32 = 20;
+/// Maximum points a caller can give for value plausibility
+pub const PLAUSIBILITY_MAX: u32 = 30;
+const MODULE_SCORE: u32 = 10;
+const MODULE_NAME_SCORE: u32 = 15;
+const NEARBY_STRING_SCORE: u32 = 15;
+const ALIGNED_4_SCORE: u32 = 10;
+const ALIGNED_2_SCORE: u32 = 5;
+/// How far a related string may be from the finding
+const NEARBY_DISTANCE: usize = 0x1000;
+
+/// What a finding is about; picks the strings and module names that support it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topic {
+    Fan,
+    Voltage,
+    Gpu,
+}
+
+impl Topic {
+    const ALL: [Topic; 3] = [Topic::Fan, Topic::Voltage, Topic::Gpu];
+
+    fn keywords(self) -> &'static [&'static str] {
+        match self {
+            Topic::Fan => &["Fan", "FAN", "Thermal", "THERMAL", "Temp"],
+            Topic::Voltage => &["Volt", "VOLT", "VDD", "Vid", "SVI"],
+            Topic::Gpu => &["Gfx", "GFX", "Gpu", "GPU", "Sclk", "SCLK"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Confidence {
+    /// 0..=100
+    pub score: u32,
+    pub reasons: Vec<String>,
+    /// Region kind at the finding
+    pub region: RegionKind,
+}
+
+/// Image-wide data shared by all scorers, built once per analysis
+pub struct ScoringContext {
+    /// Findings scoring below this are not reported
+    pub min_score: u32,
+    /// Report findings outside data regions too
+    pub all_regions: bool,
+    pub regions: RegionMap,
+    /// (start, end, name) of every FFS file
+    modules: Vec<(usize, usize, String)>,
+    /// Sorted ASCII / UTF-16 offsets of each topic's keywords
+    strings: Vec<(Topic, Vec<(usize, &'static str)>)>,
+}
+
+impl ScoringContext {
+    pub fn new(data: &[u8], min_score: u32, all_regions: bool) -> Self {
+        let volumes = parse_firmware_volumes(data);
+        let mut modules: Vec<(usize, usize, String)> = all_files(&volumes).iter()
+            .map(|f| (f.offset, f.offset + f.size, f.name.clone().unwrap_or_else(|| f.guid.clone())))
+            .collect();
+        modules.sort_by_key(|m| m.0);
+        let strings = Topic::ALL.iter()
+            .map(|&topic| {
+                let mut offsets: Vec<(usize, &'static str)> = topic.keywords().iter()
+                    .flat_map(|&k| {
+                        let utf16: Vec<u8> = k.bytes().flat_map(|b| [b, 0]).collect();
+                        find_pattern(data, k.as_bytes()).into_iter()
+                            .chain(find_pattern(data, &utf16))
+                            .map(move |o| (o, k))
+                    })
+                    .collect();
+                offsets.sort_unstable();
+                (topic, offsets)
+            })
+            .collect();
+        ScoringContext { min_score, all_regions, regions: RegionMap::new(data), modules, strings }
+    }
+
+    /// Whether table heuristics may report a hit at `offset`: data regions only, unless all regions were asked for
+    pub fn allows(&self, offset: usize) -> bool {
+        self.all_regions || self.regions.kind_at(offset) == RegionKind::Data
+    }
+
+    /// Innermost FFS file containing `offset`
+    fn module_at(&self, offset: usize) -> Option<&str> {
+        self.modules.iter()
+            .filter(|(start, end, _)| (*start..*end).contains(&offset))
+            .min_by_key(|(start, end, _)| end - start)
+            .map(|(_, _, name)| name.as_str())
+    }
+
+    /// Closest keyword of `topic` within NEARBY_DISTANCE
+    fn nearby_string(&self, offset: usize, topic: Topic) -> Option<(usize, &'static str)> {
+        let (_, offsets) = self.strings.iter().find(|(t, _)| *t == topic)?;
+        let i = offsets.partition_point(|&(o, _)| o < offset);
+        offsets[i.saturating_sub(1)..(i + 1).min(offsets.len())].iter()
+            .map(|&(o, k)| (o.abs_diff(offset), k))
+            .filter(|&(d, _)| d <= NEARBY_DISTANCE)
+            .min()
+    }
+
+    /// Score a finding at `offset`. `plausibility` is the caller's own value check:
+    /// points up to PLAUSIBILITY_MAX and the reasons for them.
+    pub fn score(&self, offset: usize, topic: Topic, plausibility: (u32, Vec<String>)) -> Confidence {
+        let (points, mut reasons) = plausibility;
+        let mut score = BASE_SCORE + points.min(PLAUSIBILITY_MAX);
+        if let Some(module) = self.module_at(offset) {
+            score += MODULE_SCORE;
+            let upper = module.to_ascii_uppercase();
+            if topic.keywords().iter().any(|k| upper.contains(&k.to_ascii_uppercase())) {
+                score += MODULE_NAME_SCORE;
+                reasons.push(format!("inside {} module", module));
+            } else {
+                reasons.push(format!("inside module {}", module));
+            }
+        }
+        if let Some((distance, keyword)) = self.nearby_string(offset, topic) {
+            score += NEARBY_STRING_SCORE;
+            reasons.push(format!("'{}' 0x{:X} bytes away", keyword, distance));
+        }
+        if offset.is_multiple_of(4) {
+            score += ALIGNED_4_SCORE;
+            reasons.push("4-byte aligned".to_string());
+        } else if offset.is_multiple_of(2) {
+            score += ALIGNED_2_SCORE;
+            reasons.push("2-byte aligned".to_string());
+        }
+        Confidence { score: score.min(100), reasons, region: self.regions.kind_at(offset) }
+    }
+
+    pub fn passes(&self, confidence: &Confidence) -> bool {
+        confidence.score >= self.min_score && (self.all_regions || confidence.region == RegionKind::Data)
+    }
+}
+
+/// SVI2 VID step
+const VID_STEP_MV: f64 = 6.25;
+
+/// Integer mV value that is a whole number of VID steps, allowing for rounding to 1 mV
+pub fn on_vid_grid(mv: f64) -> bool {
+    (mv - (mv / VID_STEP_MV).round() * VID_STEP_MV).abs() <= 0.5
+}
+
+/// Plausibility points for `checks` (condition, points, reason), capped at PLAUSIBILITY_MAX
+pub fn plausibility(checks: &[(bool, u32, &str)]) -> (u32, Vec<String>) {
+    checks.iter()
+        .filter(|(ok, _, _)| *ok)
+        .fold((0, Vec::new()), |(points, mut reasons), &(_, p, reason)| {
+            reasons.push(reason.to_string());
+            ((points + p).min(PLAUSIBILITY_MAX), reasons)
+        })
+}
+
+/// Plausibility of a fan curve: speeds follow temperature up to full speed
+pub fn fan_curve_plausibility(temps: &[u8], speeds: &[u8]) -> (u32, Vec<String>) {
+    plausibility(&[
+        (speeds.windows(2).all(|w| w[0] <= w[1]) && speeds[0] < speeds[speeds.len() - 1], 15, "speeds rise with temperature"),
+        (speeds.iter().any(|&s| s == 100 || s == 255), 10, "reaches full speed"),
+        (temps.iter().all(|t| t % 5 == 0), 5, "temperatures on 5 °C steps"),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    const RAW_DATA: usize = 0x48 + 0x18;
+
+    /// Volume with a "FanTable" module: RAW section of table bytes, then its UI section
+    fn image() -> Vec<u8> {
+        let table: Vec<u8> = [0x28, 0x00, 0x50, 0x00].iter().copied().cycle().take(0x200).collect();
+        let name: Vec<u8> = "FanTable\0".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let sections = [section(SECTION_RAW, &table), section(SECTION_USER_INTERFACE, &name)].concat();
+        volume(&[ffs_file(0x44, &sections)], 0x3000)
+    }
+
+    #[test]
+    fn on_vid_grid_allows_rounding_to_1_mv() {
+        assert!(on_vid_grid(1100.0));
+        assert!(on_vid_grid(1106.25));
+        assert!(on_vid_grid(1106.0));
+        assert!(!on_vid_grid(1103.0));
+        assert!(!on_vid_grid(1109.0));
+    }
+
+    #[test]
+    fn plausibility_is_capped() {
+        let (points, reasons) = plausibility(&[(true, 20, "a"), (false, 5, "b"), (true, 20, "c")]);
+        assert_eq!((points, reasons), (PLAUSIBILITY_MAX, vec!["a".to_string(), "c".to_string()]));
+        assert_eq!(fan_curve_plausibility(&[40, 60, 80], &[20, 50, 100]).0, 30);
+        assert_eq!(fan_curve_plausibility(&[41, 60, 80], &[50, 20, 60]).0, 0);
+    }
+
+    #[test]
+    fn score_adds_module_string_and_alignment() {
+        let data = image();
+        let context = ScoringContext::new(&data, 50, false);
+
+        let inside = context.score(RAW_DATA + 4 + 0x100, Topic::Fan, (10, vec!["plausible".to_string()]));
+        assert_eq!(inside.region, RegionKind::Data);
+        assert_eq!(inside.score, BASE_SCORE + 10 + MODULE_SCORE + MODULE_NAME_SCORE + NEARBY_STRING_SCORE + ALIGNED_4_SCORE);
+        assert_eq!(inside.reasons[..2], ["plausible", "inside FanTable module"]);
+        assert!(inside.reasons[2].starts_with("'Fan' 0x"), "{:?}", inside.reasons);
+        assert_eq!(inside.reasons[3], "4-byte aligned");
+        assert!(context.passes(&inside));
+
+        // Another topic gets the module points but not the name or string points
+        let voltage = context.score(RAW_DATA + 4 + 0x102, Topic::Voltage, (0, Vec::new()));
+        assert_eq!(voltage.score, BASE_SCORE + MODULE_SCORE + ALIGNED_2_SCORE);
+        assert_eq!(voltage.reasons, ["inside module FanTable", "2-byte aligned"]);
+        assert!(!context.passes(&voltage));
+
+        // Free space far from the module: only the base score, in padding
+        let free = context.score(0x2F01, Topic::Fan, (0, Vec::new()));
+        assert_eq!((free.score, free.region), (BASE_SCORE, RegionKind::Padding));
+        assert!(free.reasons.is_empty());
+    }
+
+    #[test]
+    fn passes_needs_score_and_data_region() {
+        let data = image();
+        let confidence = |score, region| Confidence { score, reasons: Vec::new(), region };
+        let context = ScoringContext::new(&data, 50, false);
+        assert!(context.passes(&confidence(50, RegionKind::Data)));
+        assert!(!context.passes(&confidence(49, RegionKind::Data)));
+        assert!(!context.passes(&confidence(90, RegionKind::Code)));
+        let all_regions = ScoringContext::new(&data, 50, true);
+        assert!(all_regions.passes(&confidence(90, RegionKind::Code)));
+        assert!(all_regions.allows(0x2F00) && !context.allows(0x2F00));
+        assert!(context.allows(RAW_DATA + 4));
+    }
+}
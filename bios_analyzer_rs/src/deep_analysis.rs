@@ -1,12 +1,16 @@
 //! Deep analysis functions for advanced BIOS structures
 
+use crate::analysis::*;
+use crate::confidence::*;
+use crate::patterns::*;
+use crate::spd::*;
 use crate::structures::*;
 use byteorder::{LittleEndian, ReadBytesExt};
 use colored::Colorize;
 use std::io::Cursor;
 
 /// Extended analysis - GPU, Voltages, Timings, etc.
-pub fn deep_analyze(data: &[u8]) -> DeepAnalysisReport {
+pub fn deep_analyze(data: &[u8], scoring: &ScoringContext) -> DeepAnalysisReport {
     let mut report = DeepAnalysisReport::default();
     
     println!("\n{}", "═".repeat(80).magenta());
@@ -14,16 +18,16 @@ pub fn deep_analyze(data: &[u8]) -> DeepAnalysisReport {
     println!("{}", "═".repeat(80).magenta());
     
     // GPU Clock analysis
-    analyze_gpu_clocks(data, &mut report);
+    analyze_gpu_clocks(data, scoring, &mut report);
     
     // Voltage tables
-    analyze_voltage_tables(data, &mut report);
+    analyze_voltage_tables(data, scoring, &mut report);
     
     // Memory timing structures
     analyze_memory_timings(data, &mut report);
     
     // Fan curves
-    analyze_fan_curves(data, &mut report);
+    analyze_fan_curves(data, scoring, &mut report);
     
     // Display/Panel info
     analyze_display(data, &mut report);
@@ -54,6 +58,7 @@ pub struct GpuClockEntry {
     pub min_mhz: u32,
     pub max_mhz: u32,
     pub default_mhz: u32,
+    pub confidence: Confidence,
 }
 
 #[derive(Debug)]
@@ -61,15 +66,18 @@ pub struct VoltageTable {
     pub offset: u64,
     pub voltage_type: String,
     pub values_mv: Vec<u32>,
+    pub confidence: Confidence,
 }
 
 #[derive(Debug)]
 pub struct MemoryTiming {
     pub offset: u64,
-    pub tcl: u8,
-    pub trcd: u8,
-    pub trp: u8,
-    pub tras: u8,
+    pub tck_ps: u32,
+    pub taa_ps: u32,
+    pub trcd_ps: u32,
+    pub trpab_ps: u32,
+    pub trppb_ps: u32,
+    pub trfcab_ps: u32,
 }
 
 
@@ -78,6 +86,7 @@ pub struct FanCurve {
     pub offset: u64,
     pub temp_points: Vec<u8>,
     pub speed_points: Vec<u8>,
+    pub confidence: Confidence,
 }
 
 #[derive(Debug)]
@@ -100,7 +109,7 @@ pub struct BootEntry {
     pub description: String,
 }
 
-fn analyze_gpu_clocks(data: &[u8], report: &mut DeepAnalysisReport) {
+fn analyze_gpu_clocks(data: &[u8], scoring: &ScoringContext, report: &mut DeepAnalysisReport) {
     println!("{}", "  Analyzing GPU clocks...".dimmed());
     
     // Steam Deck GPU (RDNA2): 200-1600 MHz typical range
@@ -126,12 +135,21 @@ fn analyze_gpu_clocks(data: &[u8], report: &mut DeepAnalysisReport) {
                         if (200..=1800).contains(&v1) && 
                            (200..=1800).contains(&v2) && 
                            (200..=1800).contains(&v3) {
-                            report.gpu_clocks.push(GpuClockEntry {
-                                offset: i as u64,
-                                min_mhz: v1.min(v2).min(v3),
-                                max_mhz: v1.max(v2).max(v3),
-                                default_mhz: v2,
-                            });
+                            let (min, max) = (v1.min(v2).min(v3), v1.max(v2).max(v3));
+                            let confidence = scoring.score(i, Topic::Gpu, plausibility(&[
+                                (min < max, 10, "distinct clocks"),
+                                ([v1, v2, v3].iter().all(|v| v % 25 == 0), 10, "clocks on a 25 MHz grid"),
+                                (max <= 1600, 10, "within the 200-1600 MHz GPU range"),
+                            ]));
+                            if scoring.passes(&confidence) {
+                                report.gpu_clocks.push(GpuClockEntry {
+                                    offset: i as u64,
+                                    min_mhz: min,
+                                    max_mhz: max,
+                                    default_mhz: v2,
+                                    confidence,
+                                });
+                            }
                         }
                     }
                 }
@@ -143,11 +161,12 @@ fn analyze_gpu_clocks(data: &[u8], report: &mut DeepAnalysisReport) {
     // Deduplicate
     report.gpu_clocks.sort_by_key(|e| e.offset);
     report.gpu_clocks.dedup_by_key(|e| e.offset);
+    report.gpu_clocks.sort_by_key(|e| std::cmp::Reverse(e.confidence.score));
     
     println!("    Found {} potential GPU clock entries", report.gpu_clocks.len());
 }
 
-fn analyze_voltage_tables(data: &[u8], report: &mut DeepAnalysisReport) {
+fn analyze_voltage_tables(data: &[u8], scoring: &ScoringContext, report: &mut DeepAnalysisReport) {
     println!("{}", "  Analyzing voltage tables...".dimmed());
     
     // Look for voltage values in mV (800-1400 range typical)
@@ -178,17 +197,26 @@ fn analyze_voltage_tables(data: &[u8], report: &mut DeepAnalysisReport) {
                         }
                     }
                     if vals.len() >= 2 {
-                        report.voltage_tables.push(VoltageTable {
-                            offset: i as u64,
-                            voltage_type: desc.to_string(),
-                            values_mv: vals,
-                        });
+                        let confidence = scoring.score(i, Topic::Voltage, plausibility(&[
+                            (vals.len() >= 3, 10, "three or more values in 700-1500 mV"),
+                            (vals.iter().all(|&v| v % 5 == 0 || on_vid_grid(v as f64)), 10, "values on a 5 / 6.25 mV grid"),
+                            (vals.windows(2).all(|w| w[0] < w[1]) || vals.windows(2).all(|w| w[0] > w[1]), 10, "monotonic"),
+                        ]));
+                        if scoring.passes(&confidence) {
+                            report.voltage_tables.push(VoltageTable {
+                                offset: i as u64,
+                                voltage_type: desc.to_string(),
+                                values_mv: vals,
+                                confidence,
+                            });
+                        }
                     }
                 }
             }
         }
     }
     
+    report.voltage_tables.sort_by_key(|e| std::cmp::Reverse(e.confidence.score));
     println!("    Found {} voltage table candidates", report.voltage_tables.len());
 }
 
@@ -196,32 +224,25 @@ fn analyze_voltage_tables(data: &[u8], report: &mut DeepAnalysisReport) {
 fn analyze_memory_timings(data: &[u8], report: &mut DeepAnalysisReport) {
     println!("{}", "  Analyzing memory timings...".dimmed());
     
-    // LPDDR5 timing bytes in SPD: tAA, tRCD, tRP, tRAS
-    // Look for timing patterns near SPD signatures
-    
-    let spd_sig = [0x23u8, 0x11, 0x13, 0x0E];
-    let mut i = 0;
-    while i < data.len() - 64 {
-        if &data[i..i+4] == spd_sig {
-            // Found SPD, extract timings
-            if i + 0x20 <= data.len() {
-                let timing = MemoryTiming {
-                    offset: i as u64,
-                    tcl: data[i + 0x18],  // tAAmin
-                    trcd: data[i + 0x1A], // tRCDmin
-                    trp: data[i + 0x1B],  // tRPab
-                    tras: data[i + 0x1C], // tRPpb (using as tRAS proxy)
-                };
-                report.memory_timings.push(timing);
-            }
+    // LPDDR5 SPD timings (MTB + FTB), decoded from each SPD signature
+    for i in find_pattern(data, SPD_SIGNATURE) {
+        if let Some(spd) = decode_spd(data, i) {
+            report.memory_timings.push(MemoryTiming {
+                offset: i as u64,
+                tck_ps: spd.tck_min_ps,
+                taa_ps: spd.taa_ps,
+                trcd_ps: spd.trcd_ps,
+                trpab_ps: spd.trpab_ps,
+                trppb_ps: spd.trppb_ps,
+                trfcab_ps: spd.trfcab_ps,
+            });
         }
-        i += 1;
     }
     
     println!("    Found {} memory timing structures", report.memory_timings.len());
 }
 
-fn analyze_fan_curves(data: &[u8], report: &mut DeepAnalysisReport) {
+fn analyze_fan_curves(data: &[u8], scoring: &ScoringContext, report: &mut DeepAnalysisReport) {
     println!("{}", "  Analyzing fan curves...".dimmed());
     
     // Fan curves typically: temp1, speed1, temp2, speed2, ...
@@ -238,14 +259,19 @@ fn analyze_fan_curves(data: &[u8], report: &mut DeepAnalysisReport) {
         let valid_speeds = speeds.iter().all(|&s| s <= 100 || s == 255);
         
         if valid_temps && valid_speeds && temps[0] >= 30 && temps[7] <= 100 {
-            report.fan_curves.push(FanCurve {
-                offset: i as u64,
-                temp_points: temps,
-                speed_points: speeds,
-            });
+            let confidence = scoring.score(i, Topic::Fan, fan_curve_plausibility(&temps, &speeds));
+            if scoring.passes(&confidence) {
+                report.fan_curves.push(FanCurve {
+                    offset: i as u64,
+                    temp_points: temps,
+                    speed_points: speeds,
+                    confidence,
+                });
+            }
         }
     }
     
+    report.fan_curves.sort_by_key(|e| std::cmp::Reverse(e.confidence.score));
     println!("    Found {} potential fan curves", report.fan_curves.len());
 }
 
@@ -346,24 +372,36 @@ impl DeepAnalysisReport {
         if !self.gpu_clocks.is_empty() {
             println!("\n{}", "  GPU CLOCKS:".bold());
             for entry in self.gpu_clocks.iter().take(10) {
-                println!("    @ 0x{:08X}: {}-{} MHz (default: {})", 
-                    entry.offset, entry.min_mhz, entry.max_mhz, entry.default_mhz);
+                println!("    @ 0x{:08X}: {}-{} MHz (default: {}) [{}% {}: {}]", 
+                    entry.offset, entry.min_mhz, entry.max_mhz, entry.default_mhz,
+                    entry.confidence.score, entry.confidence.region.name(), entry.confidence.reasons.join(", "));
             }
         }
         
         if !self.voltage_tables.is_empty() {
             println!("\n{}", "  VOLTAGE TABLES:".bold());
             for entry in self.voltage_tables.iter().take(10) {
-                println!("    @ 0x{:08X}: {} - {:?}mV", 
-                    entry.offset, entry.voltage_type, entry.values_mv);
+                println!("    @ 0x{:08X}: {} - {:?}mV [{}% {}: {}]", 
+                    entry.offset, entry.voltage_type, entry.values_mv,
+                    entry.confidence.score, entry.confidence.region.name(), entry.confidence.reasons.join(", "));
+            }
+        }
+        
+        if !self.fan_curves.is_empty() {
+            println!("\n{}", "  FAN CURVES:".bold());
+            for entry in self.fan_curves.iter().take(10) {
+                println!("    @ 0x{:08X}: {:?}°C -> {:?}% [{}% {}: {}]", 
+                    entry.offset, entry.temp_points, entry.speed_points,
+                    entry.confidence.score, entry.confidence.region.name(), entry.confidence.reasons.join(", "));
             }
         }
         
         if !self.memory_timings.is_empty() {
             println!("\n{}", "  MEMORY TIMINGS:".bold());
             for entry in &self.memory_timings {
-                println!("    @ 0x{:08X}: tCL=0x{:02X} tRCD=0x{:02X} tRP=0x{:02X} tRAS=0x{:02X}", 
-                    entry.offset, entry.tcl, entry.trcd, entry.trp, entry.tras);
+                println!("    @ 0x{:08X}: tCK={}ps tAA={}ps tRCD={}ps tRPab={}ps tRPpb={}ps tRFCab={}ps", 
+                    entry.offset, entry.tck_ps, entry.taa_ps, entry.trcd_ps,
+                    entry.trpab_ps, entry.trppb_ps, entry.trfcab_ps);
             }
         }
         
@@ -0,0 +1,256 @@
+//! Structural image diff - FFS modules, PSP entries, NVRAM variables, SPDs and IFR questions aligned by identity
+
+use crate::analysis::*;
+use crate::ffs::*;
+use crate::ifr_diff::*;
+use crate::ifr_parser::*;
+use crate::nvram::*;
+use crate::patch_file::*;
+use crate::patterns::*;
+use crate::psp::*;
+use crate::spd::*;
+use colored::Colorize;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// FFS pad files hold free space, not modules
+const FILE_TYPE_PAD: u8 = 0xF0;
+
+/// A structure found in one image, identified by a key that survives relocation
+struct Item {
+    key: String,
+    offset: usize,
+    size: usize,
+    sha256: String,
+    /// Named properties compared field by field when the hashes differ
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Item {
+    fn new(key: String, offset: usize, bytes: &[u8], fields: Vec<(&'static str, String)>) -> Self {
+        Item { key, offset, size: bytes.len(), sha256: sha256_hex(bytes), fields }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffEntry {
+    pub category: String,
+    pub key: String,
+    /// "added", "removed" or "changed"
+    pub change: String,
+    pub old_offset: Option<usize>,
+    pub new_offset: Option<usize>,
+    pub old_size: Option<usize>,
+    pub new_size: Option<usize>,
+    pub size_delta: i64,
+    pub old_sha256: Option<String>,
+    pub new_sha256: Option<String>,
+    /// "field: old -> new" for properties that differ
+    pub field_changes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageDiff {
+    pub old_image: String,
+    pub new_image: String,
+    pub old_sha256: String,
+    pub new_sha256: String,
+    pub entries: Vec<DiffEntry>,
+    /// Items present and identical in both images, per category
+    pub unchanged: BTreeMap<String, usize>,
+}
+
+fn module_items(data: &[u8]) -> Vec<Item> {
+    let volumes = parse_firmware_volumes(data);
+    all_files(&volumes).into_iter()
+        .filter(|f| f.file_type != FILE_TYPE_PAD)
+        .filter_map(|f| {
+            let body = data.get(f.data_offset()..f.offset + f.size)?;
+            let key = match &f.name {
+                Some(name) => format!("{} {}", f.guid, name),
+                None => f.guid.clone(),
+            };
+            Some(Item::new(key, f.offset, body, vec![("type", format!("0x{:02X}", f.file_type))]))
+        })
+        .collect()
+}
+
+fn psp_items(data: &[u8]) -> Vec<Item> {
+    parse_psp_directories(data).iter()
+        .flat_map(|dir| dir.entries.iter().map(move |e| (dir, e)))
+        .map(|(dir, entry)| {
+            let key = format!("{} type 0x{:02X}.{}", dir.signature, entry.entry_type, entry.subprogram);
+            // Entries without a body in the image (soft fuses, values) are compared by their location field
+            let location = entry.location.to_le_bytes();
+            let (offset, bytes) = match entry.offset.and_then(|o| data.get(o..o + entry.size as usize).map(|b| (o, b))) {
+                Some(body) if entry.size != 0 => body,
+                _ => (dir.offset, &location[..]),
+            };
+            Item::new(key, offset, bytes, vec![("location", format!("0x{:X}", entry.location))])
+        })
+        .collect()
+}
+
+fn nvram_items(data: &[u8]) -> Vec<Item> {
+    parse_variable_stores(data).into_iter()
+        .flat_map(|store| store.variables)
+        .filter(|v| v.is_active())
+        .filter_map(|v| {
+            let bytes = data.get(v.data_offset..v.data_offset + v.data_size)?;
+            Some(Item::new(format!("{}:{}", v.guid, v.name), v.offset, bytes,
+                vec![("attributes", format!("0x{:08X}", v.attributes))]))
+        })
+        .collect()
+}
+
+fn spd_items(data: &[u8]) -> Vec<Item> {
+    find_pattern(data, SPD_SIGNATURE).into_iter()
+        .filter_map(|o| {
+            let info = decode_spd(data, o)?;
+            let bytes = data.get(o..o + SPD_MIN_SIZE)?;
+            let key = if info.part_number.is_empty() { "unknown part".to_string() } else { info.part_number.clone() };
+            Some(Item::new(key, o, bytes, vec![("max rate", format!("{} MT/s", info.max_data_rate))]))
+        })
+        .collect()
+}
+
+/// Collects the items of one category from an image
+type Collector = fn(&[u8]) -> Vec<Item>;
+
+const CATEGORIES: &[(&str, Collector)] = &[
+    ("module", module_items),
+    ("psp", psp_items),
+    ("nvram", nvram_items),
+    ("spd", spd_items),
+];
+
+/// Make repeated keys unique in image order ("key #2", "key #3"...), so copies pair up in order
+pub fn unique_keys(keys: impl Iterator<Item = String>) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    keys.map(|key| {
+        let n = seen.entry(key.clone()).or_insert(0);
+        *n += 1;
+        if *n > 1 { format!("{} #{}", key, n) } else { key }
+    }).collect()
+}
+
+fn number_duplicates(items: &mut [Item]) {
+    let keys = unique_keys(items.iter().map(|i| i.key.clone()));
+    for (item, key) in items.iter_mut().zip(keys) {
+        item.key = key;
+    }
+}
+
+fn align(category: &str, mut old: Vec<Item>, mut new: Vec<Item>, diff: &mut ImageDiff) {
+    number_duplicates(&mut old);
+    number_duplicates(&mut new);
+    let mut old: BTreeMap<String, Item> = old.into_iter().map(|i| (i.key.clone(), i)).collect();
+    let mut unchanged = 0;
+    let entry = |change: &str, old: Option<&Item>, new: Option<&Item>| DiffEntry {
+        category: category.to_string(),
+        key: new.or(old).map(|i| i.key.clone()).unwrap_or_default(),
+        change: change.to_string(),
+        old_offset: old.map(|i| i.offset),
+        new_offset: new.map(|i| i.offset),
+        old_size: old.map(|i| i.size),
+        new_size: new.map(|i| i.size),
+        size_delta: new.map_or(0, |i| i.size as i64) - old.map_or(0, |i| i.size as i64),
+        old_sha256: old.map(|i| i.sha256.clone()),
+        new_sha256: new.map(|i| i.sha256.clone()),
+        field_changes: match (old, new) {
+            (Some(o), Some(n)) => o.fields.iter().zip(&n.fields)
+                .filter(|(a, b)| a.1 != b.1)
+                .map(|(a, b)| format!("{}: {} -> {}", a.0, a.1, b.1))
+                .collect(),
+            _ => Vec::new(),
+        },
+    };
+    for item in &new {
+        match old.remove(&item.key) {
+            None => diff.entries.push(entry("added", None, Some(item))),
+            Some(o) if o.sha256 != item.sha256 => diff.entries.push(entry("changed", Some(&o), Some(item))),
+            Some(_) => unchanged += 1,
+        }
+    }
+    for item in old.values() {
+        diff.entries.push(entry("removed", Some(item), None));
+    }
+    diff.unchanged.insert(category.to_string(), unchanged);
+}
+
+/// Align both images structure by structure
+pub fn diff_images(old_name: &str, old: &[u8], new_name: &str, new: &[u8]) -> ImageDiff {
+    let mut diff = ImageDiff {
+        old_image: old_name.to_string(),
+        new_image: new_name.to_string(),
+        old_sha256: sha256_hex(old),
+        new_sha256: sha256_hex(new),
+        entries: Vec::new(),
+        unchanged: BTreeMap::new(),
+    };
+    for &(category, items) in CATEGORIES {
+        align(category, items(old), items(new), &mut diff);
+    }
+
+    // Setup questions have no bytes of their own worth hashing; compare them field by field
+    let (questions, unchanged) = diff_form_sets(&parse_form_sets(old), &parse_form_sets(new));
+    diff.entries.extend(questions.into_iter().map(|q| DiffEntry {
+        category: "ifr".to_string(),
+        key: q.key,
+        change: q.change,
+        old_offset: q.old_offset,
+        new_offset: q.new_offset,
+        old_size: None,
+        new_size: None,
+        size_delta: 0,
+        old_sha256: None,
+        new_sha256: None,
+        field_changes: q.changes,
+    }));
+    diff.unchanged.insert("ifr".to_string(), unchanged);
+    diff
+}
+
+fn short_hash(hash: &Option<String>) -> &str {
+    hash.as_deref().map_or("-", |h| &h[..h.len().min(12)])
+}
+
+/// `diff <old image> <new image>`
+pub fn run_diff(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer diff <old image> <new image>";
+    let [old_path, new_path] = args else { return Err(usage.into()) };
+
+    println!("{}", "═".repeat(80).cyan());
+    println!("{}", " STRUCTURAL DIFF".bold().cyan());
+    println!("{}", "═".repeat(80).cyan());
+
+    let old = std::fs::read(old_path)?;
+    let new = std::fs::read(new_path)?;
+    let diff = diff_images(old_path, &old, new_path, &new);
+
+    println!("  Old: {} ({} bytes, SHA-256 {})", old_path, old.len(), &diff.old_sha256[..16]);
+    println!("  New: {} ({} bytes, SHA-256 {})", new_path, new.len(), &diff.new_sha256[..16]);
+    for category in CATEGORIES.iter().map(|c| c.0).chain(["ifr"]) {
+        let unchanged = diff.unchanged.get(category).copied().unwrap_or(0);
+        let count = |change: &str| diff.entries.iter().filter(|e| e.category == category && e.change == change).count();
+        println!("\n  {} {}: {} added, {} removed, {} changed, {} unchanged", "■".cyan(), category.bold(),
+            count("added"), count("removed"), count("changed"), unchanged);
+        for e in diff.entries.iter().filter(|e| e.category == category) {
+            let (mark, sizes) = match e.change.as_str() {
+                "added" => ("+".green(), e.new_size.map(|n| format!(" (0x{:X} bytes, {})", n, short_hash(&e.new_sha256)))),
+                "removed" => ("-".red(), e.old_size.map(|o| format!(" (0x{:X} bytes, {})", o, short_hash(&e.old_sha256)))),
+                _ => ("~".yellow(), e.old_size.zip(e.new_size).map(|(o, n)| format!(" (0x{:X} -> 0x{:X}, {:+} bytes, {} -> {})",
+                    o, n, e.size_delta, short_hash(&e.old_sha256), short_hash(&e.new_sha256)))),
+            };
+            println!("    {} {}{}", mark, e.key, sizes.unwrap_or_default());
+            for change in &e.field_changes {
+                println!("        {}", change);
+            }
+        }
+    }
+
+    let path = format!("{}.structdiff.json", new_path);
+    std::fs::write(&path, serde_json::to_string_pretty(&diff)?)?;
+    println!("\n{}", format!("Diff saved to {}", path).green());
+    Ok(())
+}
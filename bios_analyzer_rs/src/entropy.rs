@@ -0,0 +1,128 @@
+//! Byte entropy map - Shannon entropy per 4 KiB block, text strip and SVG rendering
+
+use crate::regions::*;
+use colored::Colorize;
+use memmap2::Mmap;
+use std::fmt::Write;
+use std::fs::File;
+
+pub const ENTROPY_BLOCK: usize = 0x1000;
+
+/// Blocks at or above this many bits per byte are compressed or encrypted
+pub const HIGH_ENTROPY: f64 = 7.2;
+
+/// Upper bound (exclusive) and what a block in the band usually is. Rough guide:
+/// tables and strings repeat a few byte values, x86 code uses most of them,
+/// LZMA/Tiano output and encrypted PSP firmware are near 8.
+const ENTROPY_BANDS: &[(f64, &str)] = &[
+    (1.0, "free space"),
+    (5.5, "tables / strings"),
+    (HIGH_ENTROPY, "code / mixed"),
+    (f64::INFINITY, "compressed / encrypted"),
+];
+
+/// Strip character per whole bit of entropy, 0..=8
+const STRIP_CHARS: [char; 9] = [' ', '.', ':', '-', '=', '+', '*', '#', '@'];
+const STRIP_WIDTH: usize = 64;
+
+/// SVG grid: blocks per row and cell size in pixels
+const SVG_COLUMNS: usize = 128;
+const SVG_CELL: usize = 6;
+
+/// Shannon entropy in bits per byte, 0..=8
+pub fn shannon_entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &b in bytes {
+        counts[b as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts.iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+/// Entropy of every ENTROPY_BLOCK of the image, rounded to 0.001
+pub fn entropy_map(data: &[u8]) -> Vec<f64> {
+    data.chunks(ENTROPY_BLOCK)
+        .map(|block| (shannon_entropy(block) * 1000.0).round() / 1000.0)
+        .collect()
+}
+
+pub fn entropy_band(entropy: f64) -> &'static str {
+    ENTROPY_BANDS.iter().find(|&&(max, _)| entropy < max).map_or("", |&(_, name)| name)
+}
+
+/// Number of blocks in each band
+pub fn entropy_summary(entropy: &[f64]) -> Vec<(&'static str, usize)> {
+    ENTROPY_BANDS.iter()
+        .map(|&(_, name)| (name, entropy.iter().filter(|&&e| entropy_band(e) == name).count()))
+        .collect()
+}
+
+/// One character per block, STRIP_WIDTH blocks per line with the line's image offset
+pub fn entropy_strip(entropy: &[f64]) -> String {
+    let mut strip = String::new();
+    for (i, line) in entropy.chunks(STRIP_WIDTH).enumerate() {
+        let chars: String = line.iter().map(|&e| STRIP_CHARS[(e as usize).min(8)]).collect();
+        let _ = writeln!(strip, "  0x{:08X} |{}|", i * STRIP_WIDTH * ENTROPY_BLOCK, chars);
+    }
+    strip
+}
+
+/// Grid of blocks from blue (0 bits) to red (8 bits); hovering a cell shows its offset, entropy and region
+pub fn entropy_svg(regions: &RegionMap) -> String {
+    let entropy = &regions.entropy;
+    let rows = entropy.len().div_ceil(SVG_COLUMNS);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+        SVG_COLUMNS * SVG_CELL, rows * SVG_CELL);
+    for (i, &e) in entropy.iter().enumerate() {
+        let offset = i * ENTROPY_BLOCK;
+        let hue = 240.0 - e.min(8.0) * 30.0;
+        let _ = writeln!(svg,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"hsl({:.0},80%,{}%)\"><title>0x{:08X} {:.2} {}</title></rect>",
+            i % SVG_COLUMNS * SVG_CELL, i / SVG_COLUMNS * SVG_CELL, SVG_CELL, SVG_CELL,
+            hue, if e < 1.0 { 95 } else { 50 }, offset, e, regions.kind_at(offset).name());
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// `bios_analyzer entropy <image> [--svg <file>]`
+pub fn run_entropy(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer entropy <image> [--svg <out.svg>]";
+    let image = args.first().ok_or(usage)?;
+    let svg = match &args[1..] {
+        [] => None,
+        [flag, path] if flag == "--svg" => Some(path),
+        _ => return Err(usage.into()),
+    };
+
+    let file = File::open(image)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let data = &mmap[..];
+    let regions = RegionMap::new(data);
+
+    println!("{}", format!("Entropy per 0x{:X} bytes, one character per block:", ENTROPY_BLOCK).bold());
+    print!("{}", entropy_strip(&regions.entropy));
+    let legend: Vec<String> = STRIP_CHARS.iter().enumerate()
+        .map(|(bits, c)| format!("'{}' {}", c, bits))
+        .collect();
+    println!("  bits/byte: {}", legend.join("  "));
+    for (band, blocks) in entropy_summary(&regions.entropy) {
+        println!("  {:<24} {} blocks", band, blocks);
+    }
+
+    if let Some(path) = svg {
+        std::fs::write(path, entropy_svg(&regions))?;
+        println!("{} {}", "SVG written to".green(), path);
+    }
+    Ok(())
+}
@@ -0,0 +1,417 @@
+//! Firmware volume / FFS file / section parser (PI spec, FFS2/FFS3)
+
+use crate::analysis::*;
+use serde::Serialize;
+
+/// FV header field offsets
+const FV_LENGTH: usize = 0x20;
+const FV_SIGNATURE: usize = 0x28;
+const FV_ATTRIBUTES: usize = 0x2C;
+const FV_HEADER_LENGTH: usize = 0x30;
+const FV_EXT_HEADER_OFFSET: usize = 0x34;
+const FV_REVISION: usize = 0x37;
+const EFI_FVB2_ERASE_POLARITY: u32 = 0x800;
+
+/// FFS file attributes
+pub const FFS_ATTRIB_LARGE_FILE: u8 = 0x01;
+pub const FFS_ATTRIB_CHECKSUM: u8 = 0x40;
+/// File checksum value when FFS_ATTRIB_CHECKSUM is clear
+pub const FFS_FIXED_CHECKSUM: u8 = 0xAA;
+
+/// FFS header field offsets
+pub const FFS_HEADER_CHECKSUM: usize = 0x10;
+pub const FFS_FILE_CHECKSUM: usize = 0x11;
+pub const FFS_STATE: usize = 0x17;
+
+/// FV header checksum field
+pub const FV_CHECKSUM: usize = 0x32;
+
+/// FFS file types without a section stream
+const FILE_TYPE_RAW: u8 = 0x01;
+const FILE_TYPE_PAD: u8 = 0xF0;
+
+/// Section types
+pub const SECTION_COMPRESSION: u8 = 0x01;
+pub const SECTION_GUID_DEFINED: u8 = 0x02;
+pub const SECTION_PE32: u8 = 0x10;
+pub const SECTION_PIC: u8 = 0x11;
+pub const SECTION_TE: u8 = 0x12;
+pub const SECTION_USER_INTERFACE: u8 = 0x15;
+pub const SECTION_FV_IMAGE: u8 = 0x17;
+
+/// GUID-defined section attribute: contents must be decoded first
+const GUIDED_PROCESSING_REQUIRED: u16 = 0x01;
+
+/// CRC32 GUID-defined section (EFI_CRC32_GUIDED_SECTION_EXTRACTION_GUID)
+pub const CRC32_SECTION_GUID: &str = "FC1BCDB0-7D31-49AA-936A-A4600D9DD083";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FirmwareVolume {
+    pub offset: usize,
+    pub length: usize,
+    /// File system GUID (FFS2 / FFS3 / NVRAM ...)
+    pub guid: String,
+    /// FvName from the extended header, if present
+    pub name: Option<String>,
+    pub attributes: u32,
+    pub header_length: usize,
+    pub revision: u8,
+    pub header_checksum_valid: bool,
+    pub files: Vec<FfsFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FfsFile {
+    pub offset: usize,
+    pub size: usize,
+    pub header_size: usize,
+    pub guid: String,
+    pub file_type: u8,
+    pub attributes: u8,
+    pub state: u8,
+    /// Module name from the USER_INTERFACE section
+    pub name: Option<String>,
+    pub sections: Vec<FfsSection>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FfsSection {
+    pub offset: usize,
+    pub size: usize,
+    pub header_size: usize,
+    pub section_type: u8,
+    /// Definition GUID of a GUID-defined section
+    pub guid: Option<String>,
+    /// Encapsulated sections that are stored in the clear
+    pub children: Vec<FfsSection>,
+    /// Volumes inside an FV_IMAGE section
+    pub volumes: Vec<FirmwareVolume>,
+}
+
+impl FirmwareVolume {
+    pub fn erase_byte(&self) -> u8 {
+        if self.attributes & EFI_FVB2_ERASE_POLARITY != 0 { 0xFF } else { 0x00 }
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        offset >= self.offset && offset < self.offset + self.length
+    }
+}
+
+impl FfsFile {
+    pub fn data_offset(&self) -> usize {
+        self.offset + self.header_size
+    }
+}
+
+impl FfsSection {
+    pub fn data_offset(&self) -> usize {
+        self.offset + self.header_size
+    }
+}
+
+pub fn section_type_name(section_type: u8) -> &'static str {
+    match section_type {
+        0x01 => "COMPRESSION",
+        0x02 => "GUID_DEFINED",
+        0x03 => "DISPOSABLE",
+        0x10 => "PE32",
+        0x11 => "PIC",
+        0x12 => "TE",
+        0x13 => "DXE_DEPEX",
+        0x14 => "VERSION",
+        0x15 => "UI",
+        0x16 => "COMPAT16",
+        0x17 => "FV_IMAGE",
+        0x18 => "FREEFORM_SUBTYPE_GUID",
+        0x19 => "RAW",
+        0x1B => "PEI_DEPEX",
+        0x1C => "MM_DEPEX",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Section type by name ("PE32", "raw", ...) for recipe locators
+pub fn section_type_by_name(name: &str) -> Option<u8> {
+    (0u8..=0x1C).find(|&t| section_type_name(t) != "UNKNOWN" && section_type_name(t).eq_ignore_ascii_case(name))
+}
+
+fn align(value: usize, to: usize) -> usize {
+    (value + to - 1) & !(to - 1)
+}
+
+/// 8-bit sum of a byte range
+pub fn sum8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b))
+}
+
+/// 16-bit sum of a little-endian word range
+pub fn sum16(bytes: &[u8]) -> u16 {
+    bytes.chunks_exact(2)
+        .fold(0u16, |acc, w| acc.wrapping_add(u16::from_le_bytes([w[0], w[1]])))
+}
+
+/// Find and parse all top-level firmware volumes in the image
+pub fn parse_firmware_volumes(data: &[u8]) -> Vec<FirmwareVolume> {
+    let mut volumes: Vec<FirmwareVolume> = Vec::new();
+    for sig in find_pattern(data, b"_FVH") {
+        if sig < FV_SIGNATURE {
+            continue;
+        }
+        let offset = sig - FV_SIGNATURE;
+        if volumes.iter().any(|v| v.contains(offset)) {
+            continue;
+        }
+        if let Some(fv) = parse_volume(data, offset) {
+            volumes.push(fv);
+        }
+    }
+    volumes
+}
+
+/// Parse one firmware volume whose header starts at `offset`
+pub fn parse_volume(data: &[u8], offset: usize) -> Option<FirmwareVolume> {
+    if data.get(offset + FV_SIGNATURE..offset + FV_SIGNATURE + 4)? != b"_FVH" {
+        return None;
+    }
+    let length = usize::try_from(read_u64(data, offset + FV_LENGTH)?).ok()?;
+    let attributes = read_u32(data, offset + FV_ATTRIBUTES)?;
+    let header_length = read_u16(data, offset + FV_HEADER_LENGTH)? as usize;
+    let ext_header_offset = read_u16(data, offset + FV_EXT_HEADER_OFFSET)? as usize;
+    let revision = *data.get(offset + FV_REVISION)?;
+
+    if header_length < 0x48 || !header_length.is_multiple_of(2) || length < header_length
+        || offset.checked_add(length)? > data.len() {
+        return None;
+    }
+
+    let header_checksum_valid = sum16(&data[offset..offset + header_length]) == 0;
+    let guid = format_guid(&data[offset + 0x10..offset + 0x20]);
+
+    let mut files_start = offset + header_length;
+    let mut name = None;
+    if ext_header_offset != 0 && ext_header_offset + 20 <= length {
+        let ext = offset + ext_header_offset;
+        name = Some(format_guid(&data[ext..ext + 16]));
+        let ext_size = read_u32(data, ext + 16)? as usize;
+        files_start = files_start.max(ext + ext_size);
+    }
+
+    let mut fv = FirmwareVolume {
+        offset,
+        length,
+        guid,
+        name,
+        attributes,
+        header_length,
+        revision,
+        header_checksum_valid,
+        files: Vec::new(),
+    };
+    fv.files = parse_files(data, files_start, offset + length, fv.erase_byte());
+    Some(fv)
+}
+
+fn parse_files(data: &[u8], start: usize, end: usize, erase: u8) -> Vec<FfsFile> {
+    let mut files = Vec::new();
+    let mut pos = align(start, 8);
+    while pos + 0x18 <= end {
+        let header = &data[pos..pos + 0x18];
+        if header.iter().all(|&b| b == erase) {
+            break; // free space
+        }
+
+        let attributes = header[0x13];
+        let mut size = read_u24(data, pos + 0x14).unwrap_or(0) as usize;
+        let mut header_size = 0x18;
+        if attributes & FFS_ATTRIB_LARGE_FILE != 0 && size == 0 {
+            size = read_u64(data, pos + 0x18).and_then(|s| usize::try_from(s).ok()).unwrap_or(0);
+            header_size = 0x20;
+        }
+        if size < header_size || pos.checked_add(size).is_none_or(|e| e > end) {
+            break;
+        }
+
+        let file_type = header[0x12];
+        let state = if erase == 0xFF { !header[0x17] } else { header[0x17] };
+        let sections = if file_type != FILE_TYPE_RAW && file_type != FILE_TYPE_PAD {
+            parse_sections(data, pos + header_size, pos + size)
+        } else {
+            Vec::new()
+        };
+        let name = find_ui_name(data, &sections);
+
+        files.push(FfsFile {
+            offset: pos,
+            size,
+            header_size,
+            guid: format_guid(&header[..16]),
+            file_type,
+            attributes,
+            state,
+            name,
+            sections,
+        });
+        pos = align(pos + size, 8);
+    }
+    files
+}
+
+fn parse_sections(data: &[u8], start: usize, end: usize) -> Vec<FfsSection> {
+    let mut sections = Vec::new();
+    let mut pos = start;
+    while pos + 4 <= end {
+        let mut size = read_u24(data, pos).unwrap_or(0) as usize;
+        let section_type = data[pos + 3];
+        let mut header_size = 4;
+        if size == 0xFFFFFF {
+            size = read_u32(data, pos + 4).unwrap_or(0) as usize;
+            header_size = 8;
+        }
+        if size < header_size || pos.checked_add(size).is_none_or(|e| e > end) {
+            break;
+        }
+        // A compression header carries 5 more bytes than the common header
+        if section_type == SECTION_COMPRESSION && size < header_size + 5 {
+            break;
+        }
+
+        let mut section = FfsSection {
+            offset: pos,
+            size,
+            header_size,
+            section_type,
+            guid: None,
+            children: Vec::new(),
+            volumes: Vec::new(),
+        };
+
+        match section_type {
+            // Compression type 0 means the payload is stored uncompressed
+            SECTION_COMPRESSION if data.get(pos + header_size + 4) == Some(&0) => {
+                section.header_size += 5;
+                section.children = parse_sections(data, section.data_offset(), pos + size);
+            }
+            SECTION_GUID_DEFINED => {
+                let guid_at = pos + header_size;
+                if guid_at + 20 <= pos + size {
+                    let guid = format_guid(&data[guid_at..guid_at + 16]);
+                    let data_offset = read_u16(data, guid_at + 16).unwrap_or(0) as usize;
+                    let guided_attrs = read_u16(data, guid_at + 18).unwrap_or(0);
+                    if data_offset >= header_size + 20 && data_offset <= size {
+                        section.header_size = data_offset;
+                        if guided_attrs & GUIDED_PROCESSING_REQUIRED == 0 || guid == CRC32_SECTION_GUID {
+                            section.children = parse_sections(data, pos + data_offset, pos + size);
+                        }
+                    }
+                    section.guid = Some(guid);
+                }
+            }
+            SECTION_FV_IMAGE => {
+                if let Some(fv) = parse_volume(&data[..pos + size], pos + header_size) {
+                    section.volumes.push(fv);
+                }
+            }
+            _ => {}
+        }
+
+        sections.push(section);
+        pos = align(pos + size, 4);
+    }
+    sections
+}
+
+fn find_ui_name(data: &[u8], sections: &[FfsSection]) -> Option<String> {
+    for section in sections {
+        if section.section_type == SECTION_USER_INTERFACE {
+            let raw = &data[section.data_offset()..section.offset + section.size];
+            let units: Vec<u16> = raw.chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0)
+                .collect();
+            return Some(String::from_utf16_lossy(&units));
+        }
+        if let Some(name) = find_ui_name(data, &section.children) {
+            return Some(name);
+        }
+    }
+    None
+}
+
+/// All files in the volumes, including those inside nested FV_IMAGE sections
+pub fn all_files(volumes: &[FirmwareVolume]) -> Vec<&FfsFile> {
+    fn walk_sections<'a>(sections: &'a [FfsSection], out: &mut Vec<&'a FfsFile>) {
+        for section in sections {
+            walk_sections(&section.children, out);
+            for fv in &section.volumes {
+                walk_volume(fv, out);
+            }
+        }
+    }
+    fn walk_volume<'a>(fv: &'a FirmwareVolume, out: &mut Vec<&'a FfsFile>) {
+        for file in &fv.files {
+            out.push(file);
+            walk_sections(&file.sections, out);
+        }
+    }
+
+    let mut out = Vec::new();
+    for fv in volumes {
+        walk_volume(fv, &mut out);
+    }
+    out
+}
+
+/// All volumes, including those nested in FV_IMAGE sections
+pub fn all_volumes(volumes: &[FirmwareVolume]) -> Vec<&FirmwareVolume> {
+    let mut out = Vec::new();
+    for fv in volumes {
+        out.push(fv);
+        for file in &fv.files {
+            for section in flatten_sections(&file.sections) {
+                out.extend(all_volumes(&section.volumes));
+            }
+        }
+    }
+    out
+}
+
+/// Sections of a file flattened in stream order, encapsulated ones included
+pub fn flatten_sections(sections: &[FfsSection]) -> Vec<&FfsSection> {
+    let mut out = Vec::new();
+    for section in sections {
+        out.push(section);
+        out.extend(flatten_sections(&section.children));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    #[test]
+    fn parse_files_stops_at_oversized_large_file() {
+        let mut fv = volume(&[ffs_file(0x22, &section(SECTION_RAW, b"data"))], 0x1000);
+        let file = 0x48;
+        fv[file + 0x13] |= FFS_ATTRIB_LARGE_FILE;
+        fv[file + 0x14..file + 0x17].fill(0);
+        fv[file + 0x18..file + 0x20].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(parse_firmware_volumes(&fv)[0].files.is_empty());
+    }
+
+    #[test]
+    fn parse_sections_rejects_truncated_compression_section() {
+        // 4-byte header plus 4 of the 5 compression header bytes
+        let short = [0x08, 0x00, 0x00, SECTION_COMPRESSION, 0, 0, 0, 0];
+        assert!(parse_sections(&short, 0, short.len()).is_empty());
+
+        let mut body = vec![0; 5];
+        body.extend(section(SECTION_RAW, b"data"));
+        let full = section(SECTION_COMPRESSION, &body);
+        let sections = parse_sections(&full, 0, full.len());
+        assert_eq!(sections[0].header_size, 9);
+        assert_eq!(sections[0].children[0].section_type, SECTION_RAW);
+    }
+}
@@ -0,0 +1,193 @@
+//! HII string package parser - resolves IFR string IDs to text
+
+use crate::analysis::*;
+use std::collections::HashMap;
+
+/// EFI_HII_PACKAGE_STRINGS
+const HII_PACKAGE_STRINGS: u8 = 0x04;
+const STRING_PACKAGE_LANGUAGE: usize = 46;
+
+/// String information block types (SIBT_*)
+const SIBT_END: u8 = 0x00;
+const SIBT_STRING_SCSU: u8 = 0x10;
+const SIBT_STRING_SCSU_FONT: u8 = 0x11;
+const SIBT_STRINGS_SCSU: u8 = 0x12;
+const SIBT_STRINGS_SCSU_FONT: u8 = 0x13;
+const SIBT_STRING_UCS2: u8 = 0x14;
+const SIBT_STRING_UCS2_FONT: u8 = 0x15;
+const SIBT_STRINGS_UCS2: u8 = 0x16;
+const SIBT_STRINGS_UCS2_FONT: u8 = 0x17;
+const SIBT_DUPLICATE: u8 = 0x20;
+const SIBT_SKIP2: u8 = 0x21;
+const SIBT_SKIP1: u8 = 0x22;
+const SIBT_EXT1: u8 = 0x30;
+const SIBT_EXT2: u8 = 0x31;
+const SIBT_EXT4: u8 = 0x32;
+
+#[derive(Debug, Clone)]
+pub struct StringPackage {
+    pub offset: usize,
+    pub strings: HashMap<u16, String>,
+    /// ID the next appended string gets
+    pub next_id: u16,
+    /// Image offset of the SIBT_END block, where new strings can be inserted
+    pub end_block: Option<usize>,
+}
+
+impl StringPackage {
+    pub fn get(&self, id: u16) -> String {
+        self.strings.get(&id).cloned().unwrap_or_else(|| format!("STR_0x{:04X}", id))
+    }
+}
+
+/// Find all HII string packages for `language` ("en-US")
+pub fn parse_string_packages(data: &[u8], language: &str) -> Vec<StringPackage> {
+    let mut tag = language.as_bytes().to_vec();
+    tag.push(0);
+
+    let mut packages = Vec::new();
+    for hit in find_pattern(data, &tag) {
+        if hit < STRING_PACKAGE_LANGUAGE {
+            continue;
+        }
+        let offset = hit - STRING_PACKAGE_LANGUAGE;
+        if let Some(package) = parse_string_package(data, offset) {
+            packages.push(package);
+        }
+    }
+    packages
+}
+
+fn parse_string_package(data: &[u8], offset: usize) -> Option<StringPackage> {
+    if *data.get(offset + 3)? != HII_PACKAGE_STRINGS {
+        return None;
+    }
+    let length = read_u24(data, offset)? as usize;
+    let header_size = read_u32(data, offset + 4)? as usize;
+    let info_offset = read_u32(data, offset + 8)? as usize;
+    if header_size <= STRING_PACKAGE_LANGUAGE || info_offset < header_size || info_offset >= length
+        || offset + length > data.len() {
+        return None;
+    }
+    let (strings, next_id, end) = parse_string_blocks(&data[offset + info_offset..offset + length]);
+    if strings.is_empty() {
+        return None;
+    }
+    Some(StringPackage {
+        offset,
+        strings,
+        next_id,
+        end_block: end.map(|e| offset + info_offset + e),
+    })
+}
+
+fn read_ucs2(blocks: &[u8], pos: &mut usize) -> Option<String> {
+    let mut units = Vec::new();
+    loop {
+        let unit = read_u16(blocks, *pos)?;
+        *pos += 2;
+        if unit == 0 {
+            break;
+        }
+        units.push(unit);
+    }
+    Some(String::from_utf16_lossy(&units))
+}
+
+fn read_scsu(blocks: &[u8], pos: &mut usize) -> Option<String> {
+    let len = blocks.get(*pos..)?.iter().position(|&b| b == 0)?;
+    let text = String::from_utf8_lossy(&blocks[*pos..*pos + len]).into_owned();
+    *pos += len + 1;
+    Some(text)
+}
+
+/// Walk the SIBT block list; string IDs start at 1.
+/// Returns the strings, the next free ID and the position of the SIBT_END block.
+fn parse_string_blocks(blocks: &[u8]) -> (HashMap<u16, String>, u16, Option<usize>) {
+    let mut strings = HashMap::new();
+    let mut id: u16 = 1;
+    let mut pos = 0;
+    let mut end = None;
+
+    while let Some(&block_type) = blocks.get(pos) {
+        pos += 1;
+        let parsed = match block_type {
+            SIBT_END => {
+                end = Some(pos - 1);
+                break;
+            }
+            SIBT_STRING_SCSU | SIBT_STRING_SCSU_FONT => {
+                if block_type == SIBT_STRING_SCSU_FONT {
+                    pos += 1;
+                }
+                read_scsu(blocks, &mut pos).map(|s| {
+                    strings.insert(id, s);
+                    id = id.wrapping_add(1);
+                })
+            }
+            SIBT_STRINGS_SCSU | SIBT_STRINGS_SCSU_FONT => {
+                if block_type == SIBT_STRINGS_SCSU_FONT {
+                    pos += 1;
+                }
+                read_u16(blocks, pos).and_then(|count| {
+                    pos += 2;
+                    for _ in 0..count {
+                        strings.insert(id, read_scsu(blocks, &mut pos)?);
+                        id = id.wrapping_add(1);
+                    }
+                    Some(())
+                })
+            }
+            SIBT_STRING_UCS2 | SIBT_STRING_UCS2_FONT => {
+                if block_type == SIBT_STRING_UCS2_FONT {
+                    pos += 1;
+                }
+                read_ucs2(blocks, &mut pos).map(|s| {
+                    strings.insert(id, s);
+                    id = id.wrapping_add(1);
+                })
+            }
+            SIBT_STRINGS_UCS2 | SIBT_STRINGS_UCS2_FONT => {
+                if block_type == SIBT_STRINGS_UCS2_FONT {
+                    pos += 1;
+                }
+                read_u16(blocks, pos).and_then(|count| {
+                    pos += 2;
+                    for _ in 0..count {
+                        strings.insert(id, read_ucs2(blocks, &mut pos)?);
+                        id = id.wrapping_add(1);
+                    }
+                    Some(())
+                })
+            }
+            SIBT_DUPLICATE => read_u16(blocks, pos).map(|source| {
+                pos += 2;
+                if let Some(s) = strings.get(&source).cloned() {
+                    strings.insert(id, s);
+                }
+                id = id.wrapping_add(1);
+            }),
+            SIBT_SKIP2 => read_u16(blocks, pos).map(|count| {
+                pos += 2;
+                id = id.wrapping_add(count);
+            }),
+            SIBT_SKIP1 => blocks.get(pos).map(|&count| {
+                pos += 1;
+                id = id.wrapping_add(count as u16);
+            }),
+            SIBT_EXT1 => blocks.get(pos + 1).map(|&len| pos += (len as usize).max(3) - 1),
+            SIBT_EXT2 => read_u16(blocks, pos + 1).map(|len| pos += (len as usize).max(4) - 1),
+            SIBT_EXT4 => read_u32(blocks, pos + 1).map(|len| pos += (len as usize).max(6) - 1),
+            _ => None,
+        };
+        if parsed.is_none() {
+            break;
+        }
+    }
+    (strings, id, end)
+}
+
+/// String package closest to `offset` (HII packages of one driver are stored together)
+pub fn nearest_package(packages: &[StringPackage], offset: usize) -> Option<&StringPackage> {
+    packages.iter().min_by_key(|p| p.offset.abs_diff(offset))
+}
@@ -0,0 +1,153 @@
+//! Identity pass - BIOS version, build date, AGESA, platform and SMBIOS defaults
+
+use crate::analysis::*;
+use crate::known_bios::*;
+use crate::structures::*;
+use colored::Colorize;
+use regex::bytes::Regex;
+use std::collections::BTreeMap;
+
+/// Most frequent regex match; release notes and capsule headers can mention other values
+fn most_frequent(data: &[u8], pattern: &str) -> Option<String> {
+    let re = Regex::new(pattern).ok()?;
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for m in re.find_iter(data) {
+        *counts.entry(String::from_utf8_lossy(m.as_bytes()).to_string()).or_default() += 1;
+    }
+    counts.into_iter().max_by_key(|(_, n)| *n).map(|(v, _)| v)
+}
+
+/// Find the Insyde/Valve BIOS version string (F7A0133, F7G0110, ...)
+pub fn find_bios_version(data: &[u8]) -> Option<String> {
+    most_frequent(data, r"F7[AG]\d{4}")
+}
+
+/// AGESA PI version ("AGESA!V9" marker followed by the PI name and version)
+fn find_agesa(data: &[u8]) -> Option<String> {
+    let re = Regex::new(r"AGESA!V\d\x00([\x20-\x7E]{4,48})").ok()?;
+    re.captures(data)
+        .and_then(|c| c.get(1))
+        .map(|m| String::from_utf8_lossy(m.as_bytes()).trim().to_string())
+        .or_else(|| most_frequent(data, r"[A-Za-z]+PI[- ][A-Za-z0-9-]* ?\d+\.\d+\.\d+\.\d+"))
+}
+
+/// Count ASCII and UTF-16 occurrences of a codename
+fn count_codename(data: &[u8], name: &str) -> usize {
+    let wide: Vec<u8> = name.bytes().flat_map(|b| [b, 0]).collect();
+    find_pattern(data, name.as_bytes()).len() + find_pattern(data, &wide).len()
+}
+
+/// Strings that follow an SMBIOS structure's formatted area
+fn smbios_strings(data: &[u8], start: usize) -> Option<Vec<String>> {
+    let mut strings = Vec::new();
+    let mut pos = start;
+    if data.get(pos..pos + 2)? == [0, 0] {
+        return Some(strings);
+    }
+    loop {
+        let len = data.get(pos..)?.iter().take(65).position(|&b| b == 0)?;
+        let s = &data[pos..pos + len];
+        if len == 0 || !s.iter().all(|b| (0x20..0x7F).contains(b)) {
+            return None;
+        }
+        strings.push(String::from_utf8_lossy(s).into_owned());
+        pos += len + 1;
+        if data.get(pos) == Some(&0) {
+            return Some(strings);
+        }
+        if strings.len() > 16 {
+            return None;
+        }
+    }
+}
+
+fn string_ref(strings: &[String], index: u8) -> Option<String> {
+    (index as usize).checked_sub(1).and_then(|i| strings.get(i)).cloned()
+}
+
+/// SMBIOS type 0 (BIOS Information) default structure
+fn find_smbios_bios(data: &[u8], date: &Regex) -> Option<SmbiosBios> {
+    for offset in 0..data.len().saturating_sub(0x20) {
+        let length = data[offset + 1] as usize;
+        if data[offset] != 0 || !(0x12..=0x1A).contains(&length) {
+            continue;
+        }
+        let (vendor, version, release) = (data[offset + 4], data[offset + 5], data[offset + 8]);
+        if !(1..=4).contains(&vendor) || !(1..=4).contains(&version) || !(1..=4).contains(&release) {
+            continue;
+        }
+        let Some(strings) = smbios_strings(data, offset + length) else { continue };
+        let Some(release_date) = string_ref(&strings, release) else { continue };
+        if !date.is_match(release_date.as_bytes()) {
+            continue;
+        }
+        return Some(SmbiosBios {
+            offset: offset as u64,
+            vendor: string_ref(&strings, vendor).unwrap_or_default(),
+            version: string_ref(&strings, version).unwrap_or_default(),
+            release_date,
+        });
+    }
+    None
+}
+
+/// SMBIOS type 1 (System Information) default structure
+fn find_smbios_system(data: &[u8]) -> Option<SmbiosSystem> {
+    for offset in 0..data.len().saturating_sub(0x20) {
+        let length = data[offset + 1] as usize;
+        if data[offset] != 1 || !(length == 0x19 || length == 0x1B) {
+            continue;
+        }
+        let (manufacturer, product) = (data[offset + 4], data[offset + 5]);
+        if !(1..=6).contains(&manufacturer) || !(1..=6).contains(&product) {
+            continue;
+        }
+        let Some(strings) = smbios_strings(data, offset + length) else { continue };
+        let Some(product_name) = string_ref(&strings, product) else { continue };
+        let (sku, family) = if length == 0x1B { (data[offset + 0x19], data[offset + 0x1A]) } else { (0, 0) };
+        return Some(SmbiosSystem {
+            offset: offset as u64,
+            manufacturer: string_ref(&strings, manufacturer).unwrap_or_default(),
+            product: product_name,
+            version: string_ref(&strings, data[offset + 6]).unwrap_or_default(),
+            sku: string_ref(&strings, sku).unwrap_or_default(),
+            family: string_ref(&strings, family).unwrap_or_default(),
+        });
+    }
+    None
+}
+
+pub fn analyze_identity(data: &[u8], report: &mut BiosReport) {
+    println!("{}", "Extracting BIOS identity...".dimmed());
+    let date = Regex::new(r"^(0[1-9]|1[0-2])/(0[1-9]|[12]\d|3[01])/(19|20)\d\d$").unwrap();
+
+    let version = find_bios_version(data);
+    let smbios_bios = find_smbios_bios(data, &date);
+    let smbios_system = find_smbios_system(data);
+
+    let mut codenames = BTreeMap::new();
+    for name in ["Jupiter", "Galileo"] {
+        codenames.insert(name.to_string(), count_codename(data, name));
+    }
+    // The version prefix is authoritative; codename strings only break ties
+    let platform = version.as_deref().and_then(platform_of).map(String::from)
+        .or_else(|| codenames.iter().filter(|(_, &n)| n > 0).max_by_key(|(_, &n)| n).map(|(k, _)| k.clone()));
+
+    let build_date = smbios_bios.as_ref().map(|b| b.release_date.clone())
+        .or_else(|| most_frequent(data, r"(0[1-9]|1[0-2])/(0[1-9]|[12]\d|3[01])/20\d\d"));
+
+    let identity = BiosIdentity {
+        version,
+        build_date,
+        agesa: find_agesa(data),
+        platform,
+        board: smbios_system.as_ref().map(|s| s.product.clone()),
+        codenames,
+        smbios_bios,
+        smbios_system,
+    };
+    println!("  {} {} built {} ({})", identity.version.as_deref().unwrap_or("unknown"),
+        identity.platform.as_deref().unwrap_or("unknown"), identity.build_date.as_deref().unwrap_or("?"),
+        identity.agesa.as_deref().unwrap_or("AGESA unknown"));
+    report.identity = Some(identity);
+}
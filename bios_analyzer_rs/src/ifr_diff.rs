@@ -0,0 +1,195 @@
+//! Setup menu diff - questions, options, defaults and suppression conditions between two BIOS releases
+
+use crate::diff::*;
+use crate::ifr_parser::*;
+use colored::Colorize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize)]
+pub struct IfrQuestionDiff {
+    /// "form set / form / prompt", numbered when repeated
+    pub key: String,
+    /// "added", "removed" or "changed"
+    pub change: String,
+    pub old_offset: Option<usize>,
+    pub new_offset: Option<usize>,
+    /// One line per difference; for added/removed questions a summary
+    pub changes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IfrDiff {
+    pub old_image: String,
+    pub new_image: String,
+    pub questions: Vec<IfrQuestionDiff>,
+    pub unchanged: usize,
+}
+
+/// A question with the names from its form set
+struct Located<'a> {
+    question: &'a IfrQuestion,
+    varstore: &'a str,
+}
+
+fn located_questions(form_sets: &[IfrFormSet]) -> Vec<(String, Located<'_>)> {
+    let keys = unique_keys(form_sets.iter()
+        .flat_map(|fs| fs.questions.iter().map(move |q| format!("{} / {} / {}", fs.title, q.form, q.prompt))));
+    let questions = form_sets.iter().flat_map(|fs| fs.questions.iter().map(move |q| Located {
+        question: q,
+        varstore: fs.varstores.iter().find(|v| v.id == q.varstore_id).map_or("?", |v| v.name.as_str()),
+    }));
+    keys.into_iter().zip(questions).collect()
+}
+
+fn storage(l: &Located) -> String {
+    format!("{}+0x{:X}/{}", l.varstore, l.question.var_offset, l.question.size)
+}
+
+/// Default value with the text of the option carrying it
+fn default_text(q: &IfrQuestion) -> String {
+    match q.default {
+        None => "none".to_string(),
+        Some(d) => match q.options.iter().find(|o| o.value == d) {
+            Some(o) => format!("'{}' (0x{:X})", o.text, d),
+            None => format!("0x{:X}", d),
+        },
+    }
+}
+
+fn conditions_text(conditions: &[String]) -> String {
+    if conditions.is_empty() { "always shown".to_string() } else { conditions.join(" / ") }
+}
+
+fn summary(l: &Located) -> String {
+    let q = l.question;
+    let range = if q.kind == "numeric" { format!(", range {}..{} step {}", q.min, q.max, q.step) } else { String::new() };
+    format!("{} {}{}, default {}, {} options, {}", q.kind, storage(l), range, default_text(q),
+        q.options.len(), conditions_text(&q.conditions))
+}
+
+/// Options by value; true when shown unconditionally
+fn options_by_value(q: &IfrQuestion) -> BTreeMap<u64, (&IfrOption, bool)> {
+    q.options.iter().map(|o| (o.value, (o, o.conditions.is_empty()))).collect()
+}
+
+/// Differences between two versions of a question, most important first
+fn question_changes(old: &Located, new: &Located) -> Vec<String> {
+    let (o, n) = (old.question, new.question);
+    let mut changes = Vec::new();
+    let mut field = |name: &str, a: String, b: String| {
+        if a != b {
+            changes.push(format!("{}: {} -> {}", name, a, b));
+        }
+    };
+    field("conditions", conditions_text(&o.conditions), conditions_text(&n.conditions));
+    field("default", default_text(o), default_text(n));
+    field("type", o.kind.clone(), n.kind.clone());
+    field("storage", storage(old), storage(new));
+    field("range", format!("{}..{} step {}", o.min, o.max, o.step), format!("{}..{} step {}", n.min, n.max, n.step));
+    if o.help != n.help {
+        changes.push("help text changed".to_string());
+    }
+
+    let (old_options, new_options) = (options_by_value(o), options_by_value(n));
+    for (value, &(option, shown)) in &new_options {
+        let label = format!("option '{}' (0x{:X})", option.text, value);
+        match old_options.get(value) {
+            None if shown => changes.push(format!("+ {}", label)),
+            None => changes.push(format!("+ {} under {}", label, conditions_text(&option.conditions))),
+            Some(&(before, was_shown)) => {
+                if before.text != option.text {
+                    changes.push(format!("option 0x{:X}: '{}' -> '{}'", value, before.text, option.text));
+                }
+                match (was_shown, shown) {
+                    (true, false) => changes.push(format!("{} hidden: {}", label, conditions_text(&option.conditions))),
+                    (false, true) => changes.push(format!("{} exposed, was {}", label, conditions_text(&before.conditions))),
+                    _ if before.conditions != option.conditions => changes.push(format!("{} conditions: {} -> {}",
+                        label, conditions_text(&before.conditions), conditions_text(&option.conditions))),
+                    _ => {}
+                }
+            }
+        }
+    }
+    for (value, (option, _)) in old_options.iter().filter(|(v, _)| !new_options.contains_key(v)) {
+        changes.push(format!("- option '{}' (0x{:X})", option.text, value));
+    }
+    changes
+}
+
+/// Align the questions of both images by form set, form and prompt
+pub fn diff_form_sets(old: &[IfrFormSet], new: &[IfrFormSet]) -> (Vec<IfrQuestionDiff>, usize) {
+    let mut old: BTreeMap<String, Located> = located_questions(old).into_iter().collect();
+    let mut diffs = Vec::new();
+    let mut unchanged = 0;
+    for (key, n) in located_questions(new) {
+        match old.remove(&key) {
+            None => diffs.push(IfrQuestionDiff {
+                changes: vec![summary(&n)],
+                key,
+                change: "added".to_string(),
+                old_offset: None,
+                new_offset: Some(n.question.offset),
+            }),
+            Some(o) => {
+                let changes = question_changes(&o, &n);
+                if changes.is_empty() {
+                    unchanged += 1;
+                    continue;
+                }
+                diffs.push(IfrQuestionDiff {
+                    key,
+                    change: "changed".to_string(),
+                    old_offset: Some(o.question.offset),
+                    new_offset: Some(n.question.offset),
+                    changes,
+                });
+            }
+        }
+    }
+    for (key, o) in old {
+        diffs.push(IfrQuestionDiff {
+            changes: vec![summary(&o)],
+            key,
+            change: "removed".to_string(),
+            old_offset: Some(o.question.offset),
+            new_offset: None,
+        });
+    }
+    (diffs, unchanged)
+}
+
+/// `ifr-diff <old image> <new image>`
+pub fn run_ifr_diff(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer ifr-diff <old image> <new image>";
+    let [old_path, new_path] = args else { return Err(usage.into()) };
+
+    println!("{}", "═".repeat(80).cyan());
+    println!("{}", " SETUP MENU DIFF".bold().cyan());
+    println!("{}", "═".repeat(80).cyan());
+
+    let old = std::fs::read(old_path)?;
+    let new = std::fs::read(new_path)?;
+    let (questions, unchanged) = diff_form_sets(&parse_form_sets(&old), &parse_form_sets(&new));
+    let count = |change: &str| questions.iter().filter(|q| q.change == change).count();
+    println!("  {} -> {}: {} added, {} removed, {} changed, {} unchanged questions",
+        old_path, new_path, count("added"), count("removed"), count("changed"), unchanged);
+
+    for q in &questions {
+        let mark = match q.change.as_str() {
+            "added" => "+".green(),
+            "removed" => "-".red(),
+            _ => "~".yellow(),
+        };
+        println!("\n  {} {}", mark, q.key.bold());
+        for change in &q.changes {
+            println!("      {}", change);
+        }
+    }
+
+    let diff = IfrDiff { old_image: old_path.clone(), new_image: new_path.clone(), questions, unchanged };
+    let path = format!("{}.ifrdiff.json", new_path);
+    std::fs::write(&path, serde_json::to_string_pretty(&diff)?)?;
+    println!("\n{}", format!("Setup menu diff saved to {}", path).green());
+    Ok(())
+}
@@ -0,0 +1,361 @@
+//! IFR option insertion - adds ONE_OF options and their HII strings to an existing question
+
+use crate::analysis::*;
+use crate::checksums::*;
+use crate::ffs::*;
+use crate::hii::*;
+use crate::ifr_parser::*;
+use crate::memclk::*;
+use crate::mirror::*;
+use crate::patch_engine::*;
+use crate::structures::*;
+use colored::Colorize;
+use memmap2::Mmap;
+use std::fs::File;
+
+/// EFI_HII_PACKAGE_END
+const HII_PACKAGE_END: u8 = 0xDF;
+/// EFI_HII_PACKAGE_LIST_HEADER: GUID + u32 length
+const PACKAGE_LIST_HEADER: usize = 20;
+/// How far before a form package to look for its package list header
+const PACKAGE_LIST_SEARCH: usize = 0x10000;
+const SIBT_STRING_UCS2: u8 = 0x14;
+
+/// HII package list: header, packages, END package
+struct PackageList {
+    offset: usize,
+    length: usize,
+    packages: Vec<usize>,
+}
+
+/// One option to add: value and its menu text
+pub struct NewOption {
+    pub value: u64,
+    pub label: String,
+}
+
+fn package_list_at(data: &[u8], offset: usize) -> Option<PackageList> {
+    let length = read_u32(data, offset + 16)? as usize;
+    if length < PACKAGE_LIST_HEADER + 4 || offset + length > data.len() {
+        return None;
+    }
+    let end = offset + length;
+    let mut packages = Vec::new();
+    let mut pos = offset + PACKAGE_LIST_HEADER;
+    while pos + 4 <= end {
+        let size = read_u24(data, pos)? as usize;
+        if size < 4 || pos + size > end {
+            return None;
+        }
+        packages.push(pos);
+        pos += size;
+        if data[pos - size + 3] == HII_PACKAGE_END {
+            return (pos == end).then_some(PackageList { offset, length, packages });
+        }
+    }
+    None
+}
+
+/// Package list that holds the package at `package`
+fn find_package_list(data: &[u8], package: usize) -> Option<PackageList> {
+    let last = package.checked_sub(PACKAGE_LIST_HEADER)?;
+    (last.saturating_sub(PACKAGE_LIST_SEARCH)..=last).rev()
+        .filter_map(|offset| package_list_at(data, offset))
+        .find(|list| list.packages.contains(&package))
+}
+
+fn add_u24(bytes: &mut [u8], at: usize, delta: usize) -> Result<(), String> {
+    let value = u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], 0]) as usize + delta;
+    if value > 0xFF_FFFF {
+        return Err(format!("package at +0x{:X} would exceed 16 MB", at));
+    }
+    bytes[at..at + 3].copy_from_slice(&(value as u32).to_le_bytes()[..3]);
+    Ok(())
+}
+
+/// Innermost data section holding `start..end`: a leaf section that is not an executable image
+fn data_section(volumes: &[FirmwareVolume], start: usize, end: usize) -> Result<FfsSection, String> {
+    let section = all_files(volumes).into_iter()
+        .flat_map(|f| flatten_sections(&f.sections))
+        .filter(|s| s.data_offset() <= start && end <= s.offset + s.size)
+        .min_by_key(|s| s.size)
+        .ok_or(format!("0x{:08X} is not inside an FFS section", start))?;
+    if matches!(section.section_type, SECTION_PE32 | SECTION_PIC | SECTION_TE)
+        || !section.children.is_empty() || !section.volumes.is_empty() {
+        return Err(format!("0x{:08X} is inside the {} section at 0x{:08X}, whose padding is not free space",
+            start, section_type_name(section.section_type), section.offset));
+    }
+    Ok(section.clone())
+}
+
+/// Rebuild the package list around `form_set` with `options` appended to `question`
+/// and their strings appended to `strings`. Returns the image offset and the new bytes,
+/// which grow into erased (0xFF) bytes after the list inside its own FFS section; section and
+/// file sizes stay as they are.
+pub fn insert_options(data: &[u8], form_set: &IfrFormSet, question: &IfrQuestion, strings: &StringPackage,
+    options: &[NewOption]) -> Result<(usize, Vec<u8>), String> {
+    // New options go after the last unconditional one, outside any suppressif inside the question
+    let last = question.options.iter().rev().find(|o| o.conditions.is_empty())
+        .ok_or("question has no unconditional option to copy")?;
+    let template = &data[last.offset..last.offset + (data[last.offset + 1] & 0x7F) as usize];
+    let end_block = strings.end_block.ok_or("string package has no SIBT_END block")?;
+    let list = find_package_list(data, form_set.offset)
+        .ok_or(format!("no HII package list around form package 0x{:08X}", form_set.offset))?;
+    if !list.packages.contains(&strings.offset) {
+        return Err(format!("string package 0x{:08X} is outside the package list at 0x{:08X}",
+            strings.offset, list.offset));
+    }
+
+    let mut opcodes = Vec::new();
+    let mut blocks = Vec::new();
+    for (i, option) in options.iter().enumerate() {
+        if last.value_size < 8 && option.value >> (last.value_size * 8) != 0 {
+            return Err(format!("value 0x{:X} does not fit the {}-byte option value", option.value, last.value_size));
+        }
+        let id = strings.next_id.checked_add(i as u16).ok_or("string IDs exhausted")?;
+        let mut op = template.to_vec();
+        op[2..4].copy_from_slice(&id.to_le_bytes());
+        op[4] &= !(IFR_OPTION_DEFAULT | IFR_OPTION_DEFAULT_MFG);
+        op[6..6 + last.value_size].copy_from_slice(&option.value.to_le_bytes()[..last.value_size]);
+        opcodes.extend(op);
+
+        blocks.push(SIBT_STRING_UCS2);
+        for unit in option.label.encode_utf16().chain([0]) {
+            blocks.extend(unit.to_le_bytes());
+        }
+    }
+
+    let delta = opcodes.len() + blocks.len();
+    let list_end = list.offset + list.length;
+    let section = data_section(&parse_firmware_volumes(data), list.offset, list_end)
+        .map_err(|e| format!("package list at {}", e))?;
+    let free = data.get(list_end..list_end + delta).filter(|_| list_end + delta <= section.offset + section.size);
+    if !free.is_some_and(|free| free.iter().all(|&b| b == 0xFF)) {
+        return Err(format!("needs 0x{:X} erased (0xFF) bytes after the package list at 0x{:08X}, inside its section at 0x{:08X}",
+            delta, list.offset, section.offset));
+    }
+
+    let (option_at, option_len) = (last.offset + template.len(), opcodes.len());
+    let (string_at, string_len) = (end_block, blocks.len());
+    let shifted = |offset: usize| offset - list.offset + [(option_at, option_len), (string_at, string_len)].iter()
+        .filter(|(at, _)| *at < offset)
+        .map(|(_, len)| len)
+        .sum::<usize>();
+
+    // Insert back to front so earlier positions stay valid
+    let mut inserts = [(option_at, opcodes), (string_at, blocks)];
+    inserts.sort_by_key(|(at, _)| std::cmp::Reverse(*at));
+    let mut bytes = data[list.offset..list_end].to_vec();
+    for (at, insert) in inserts {
+        let at = at - list.offset;
+        bytes.splice(at..at, insert);
+    }
+
+    // Lengths: list header, form package, string package
+    bytes[16..20].copy_from_slice(&((list.length + delta) as u32).to_le_bytes());
+    add_u24(&mut bytes, shifted(form_set.offset), option_len)?;
+    add_u24(&mut bytes, shifted(strings.offset), string_len)?;
+    Ok((list.offset, bytes))
+}
+
+/// Parse a comma separated list of hex values ("5A,5B" or "0x5A,0x5B")
+fn parse_values(list: &str) -> Result<Vec<u64>, String> {
+    list.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| u64::from_str_radix(s.trim().trim_start_matches("0x"), 16)
+            .map_err(|e| format!("bad value '{}': {}", s, e)))
+        .collect()
+}
+
+/// `add-options <input> <output> --values 5A,5B [--question "Memory Clock Speed"] [--labels a,b]`
+pub fn run_add_options(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer add-options <input> <output> --values 5A,5B,... [--question \"Memory Clock Speed\"] [--labels 3250MHZ,...]";
+    let input = args.first().ok_or(usage)?;
+    let output = args.get(1).ok_or(usage)?;
+    let mut prompt = MEMORY_CLOCK_QUESTION.to_string();
+    let mut values = Vec::new();
+    let mut labels: Option<Vec<String>> = None;
+    let mut rest = args[2..].iter();
+    while let Some(arg) = rest.next() {
+        match (arg.as_str(), rest.next()) {
+            ("--values", Some(list)) => values = parse_values(list)?,
+            ("--question", Some(text)) => prompt = text.clone(),
+            ("--labels", Some(list)) => labels = Some(list.split(',').map(|s| s.trim().to_string()).collect()),
+            _ => return Err(usage.into()),
+        }
+    }
+    if values.is_empty() {
+        return Err(usage.into());
+    }
+    let labels = match labels {
+        Some(labels) if labels.len() != values.len() => return Err("--labels needs one label per value".into()),
+        Some(labels) => labels,
+        None if prompt.eq_ignore_ascii_case(MEMORY_CLOCK_QUESTION) => values.iter()
+            .map(|&v| memclk_mhz(v as u16).map(|mhz| format!("{}MHZ", mhz)).ok_or(format!("no clock for code 0x{:X}", v)))
+            .collect::<Result<_, _>>()?,
+        None => values.iter().map(|v| format!("0x{:X}", v)).collect(),
+    };
+
+    println!("{}", "═".repeat(80).cyan());
+    println!("{}", " IFR OPTION INSERTION".bold().cyan());
+    println!("{}", "═".repeat(80).cyan());
+
+    let file = File::open(input)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let data = &mmap[..];
+    let form_sets = parse_form_sets(data);
+    let packages = parse_string_packages(data, "en-US");
+
+    let mut candidates: Vec<PatchCandidate> = Vec::new();
+    for form_set in &form_sets {
+        for question in form_set.questions.iter().filter(|q| q.prompt.eq_ignore_ascii_case(&prompt)) {
+            let new: Vec<NewOption> = values.iter().zip(&labels)
+                .filter(|(v, _)| !question.options.iter().any(|o| o.value == **v))
+                .map(|(&value, label)| NewOption { value, label: label.clone() })
+                .collect();
+            if new.is_empty() {
+                println!("    '{}' @ 0x{:08X}: all values already offered", question.prompt, question.offset);
+                continue;
+            }
+            for option in new.iter().filter(|o| o.value < question.min || o.value > question.max) {
+                println!("    {} value 0x{:X} is outside the question range 0x{:X}..0x{:X}",
+                    "warning".yellow(), option.value, question.min, question.max);
+            }
+            let Some(strings) = nearest_package(&packages, form_set.offset) else { continue };
+            let (offset, bytes) = match insert_options(data, form_set, question, strings, &new) {
+                Ok(result) => result,
+                Err(e) => {
+                    println!("    {} '{}' @ 0x{:08X}: {}", "skipped".yellow(), question.prompt, question.offset, e);
+                    continue;
+                }
+            };
+            let end = offset + bytes.len();
+            if candidates.iter().any(|c| (c.offset as usize) < end && offset < c.offset as usize + c.patched.len()) {
+                println!("    {} '{}' @ 0x{:08X}: package list already extended, run again for this question",
+                    "skipped".yellow(), question.prompt, question.offset);
+                continue;
+            }
+            println!("    '{}' @ 0x{:08X}: +{} option(s), package list 0x{:08X} grows to 0x{:X} bytes",
+                question.prompt, question.offset, new.len(), offset, bytes.len());
+            candidates.push(PatchCandidate {
+                recipe: "add-options".to_string(),
+                offset: offset as u64,
+                original: data[offset..offset + bytes.len()].to_vec(),
+                patched: bytes,
+                description: format!("Add {} to '{}'",
+                    new.iter().map(|o| o.label.as_str()).collect::<Vec<_>>().join(", "), question.prompt),
+                effect: format!("{} more menu option(s)", new.len()),
+                risk: "medium".to_string(),
+            });
+        }
+    }
+    if candidates.is_empty() {
+        return Err(format!("no '{}' question could be extended", prompt).into());
+    }
+
+    // Every A/B copy must have been extended on its own with the same bytes; copying the bytes
+    // over would skip the section checks of the other copy
+    println!("\n{}", "  Checking mirrors...".bold());
+    let selected: Vec<&PatchCandidate> = candidates.iter().collect();
+    if let Some(missing) = mirror_patches(data, &find_mirror_regions(data), &selected)?.first() {
+        return Err(format!("mirror copy at 0x{:08X} was not extended like its primary, refusing to leave the A/B copies diverged",
+            missing.offset).into());
+    }
+    let (mut patched, applied) = apply_patches(data, &selected)?;
+    let changed: Vec<(usize, usize)> = selected.iter().map(|p| (p.offset as usize, p.patched.len())).collect();
+
+    println!("\n{}", "  Fixing checksums...".bold());
+    let checksums = fix_checksums(data, &mut patched, &changed);
+    save_patched(input, output, data, &patched, applied, checksums)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+    use crate::validate::*;
+
+    /// Package list at the start of a section of `section_type`, followed by `slack` bytes of `fill`
+    fn image_filled(section_type: u8, slack: usize, fill: u8) -> Vec<u8> {
+        let body = [hii_package_list(), vec![fill; slack]].concat();
+        volume(&[ffs_file(0x22, &section(section_type, &body))], 0x1000)
+    }
+
+    fn image(section_type: u8, slack: usize) -> Vec<u8> {
+        image_filled(section_type, slack, 0xFF)
+    }
+
+    fn new_options() -> Vec<NewOption> {
+        vec![
+            NewOption { value: 0x5A, label: "3250MHZ".to_string() },
+            NewOption { value: 0x5B, label: "3300MHZ".to_string() },
+        ]
+    }
+
+    fn insert(data: &[u8]) -> Result<(usize, Vec<u8>), String> {
+        let form_sets = parse_form_sets(data);
+        let question = find_questions(&form_sets, MEMORY_CLOCK_QUESTION)[0];
+        let strings = &parse_string_packages(data, "en-US")[0];
+        insert_options(data, &form_sets[0], question, strings, &new_options())
+    }
+
+    #[test]
+    fn insert_options_round_trip() {
+        let data = image(SECTION_RAW, 0x100);
+        let list = 0x48 + 0x18 + 4;
+        let length = hii_package_list().len();
+        let (offset, bytes) = insert(&data).unwrap();
+        // Two 7-byte ONE_OF_OPTIONs and two SIBT_STRING_UCS2 blocks of 1 + 8 * 2 bytes
+        assert_eq!((offset, bytes.len()), (list, length + 2 * 7 + 2 * 17));
+        assert_eq!(read_u32(&bytes, 16), Some(bytes.len() as u32));
+
+        let mut patched = data.clone();
+        patched[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        let checksums = fix_checksums(&data, &mut patched, &[(offset, bytes.len())]);
+        assert_eq!(patched[offset + bytes.len()..0x1000], data[offset + bytes.len()..0x1000]);
+        let allowed: Vec<(usize, usize)> = std::iter::once((offset, bytes.len()))
+            .chain(checksums.iter().map(|c| (c.offset as usize, c.new.len() / 2)))
+            .collect();
+        validate_patched(&data, &patched, &allowed).unwrap();
+
+        let form_sets = parse_form_sets(&patched);
+        let question = find_questions(&form_sets, MEMORY_CLOCK_QUESTION)[0];
+        let options: Vec<(u64, &str, u8)> = question.options.iter()
+            .map(|o| (o.value, o.text.as_str(), o.flags))
+            .collect();
+        assert_eq!(options, [
+            (0xFF, "Auto", IFR_OPTION_DEFAULT),
+            (0x59, "3200MHZ", 0),
+            (0x5F, "3733MHZ", 0),
+            (0x5A, "3250MHZ", 0),
+            (0x5B, "3300MHZ", 0),
+        ]);
+        assert_eq!(question.default, Some(0xFF));
+        assert_eq!(parse_string_packages(&patched, "en-US")[0].next_id, 9);
+    }
+
+    #[test]
+    fn insert_options_needs_slack_in_the_section() {
+        let err = insert(&image(SECTION_RAW, 0x20)).unwrap_err();
+        assert!(err.contains("needs 0x30 erased (0xFF) bytes"), "{}", err);
+    }
+
+    #[test]
+    fn insert_options_refuses_zero_filled_slack() {
+        let err = insert(&image_filled(SECTION_RAW, 0x100, 0)).unwrap_err();
+        assert!(err.contains("erased (0xFF)"), "{}", err);
+    }
+
+    #[test]
+    fn insert_options_refuses_executable_sections() {
+        let err = insert(&image(SECTION_PE32, 0x100)).unwrap_err();
+        assert!(err.contains("PE32"), "{}", err);
+    }
+
+    #[test]
+    fn insert_options_refuses_lists_outside_ffs() {
+        let data = [hii_package_list(), vec![0xFF; 0x100]].concat();
+        let err = insert(&data).unwrap_err();
+        assert!(err.contains("not inside an FFS section"), "{}", err);
+    }
+}
@@ -1,6 +1,9 @@
 //! IFR (Internal Form Representation) parser - find hidden BIOS menu options
 
+use crate::analysis::*;
+use crate::hii::*;
 use colored::Colorize;
+use serde::Serialize;
 
 pub fn parse_ifr_options(data: &[u8]) {
     println!("\n{}", "═".repeat(80).bright_magenta());
@@ -267,3 +270,307 @@ fn find_hidden_frequency_options(data: &[u8]) {
         }
     }
 }
+
+// ─── Structural IFR parsing ─────────────────────────────────────────────────
+
+/// HII package type of a form package
+const HII_PACKAGE_FORMS: u8 = 0x02;
+
+/// IFR opcodes
+const IFR_FORM: u8 = 0x01;
+const IFR_ONE_OF: u8 = 0x05;
+const IFR_CHECKBOX: u8 = 0x06;
+const IFR_NUMERIC: u8 = 0x07;
+const IFR_ONE_OF_OPTION: u8 = 0x09;
+const IFR_SUPPRESS_IF: u8 = 0x0A;
+const IFR_FORM_SET: u8 = 0x0E;
+const IFR_GRAY_OUT_IF: u8 = 0x19;
+const IFR_DISABLE_IF: u8 = 0x1E;
+const IFR_VARSTORE: u8 = 0x24;
+const IFR_VARSTORE_EFI: u8 = 0x26;
+const IFR_END: u8 = 0x29;
+const IFR_DEFAULT: u8 = 0x5B;
+
+/// ONE_OF_OPTION flag: option is the standard default
+pub const IFR_OPTION_DEFAULT: u8 = 0x10;
+/// ONE_OF_OPTION flag: option is the manufacturing default
+pub const IFR_OPTION_DEFAULT_MFG: u8 = 0x20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IfrFormSet {
+    pub offset: usize,
+    pub guid: String,
+    pub title: String,
+    pub varstores: Vec<IfrVarStore>,
+    pub questions: Vec<IfrQuestion>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IfrVarStore {
+    pub id: u16,
+    pub guid: String,
+    pub name: String,
+    pub size: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IfrQuestion {
+    pub offset: usize,
+    /// "one_of", "numeric" or "checkbox"
+    pub kind: String,
+    pub prompt: String,
+    pub help: String,
+    pub question_id: u16,
+    pub varstore_id: u16,
+    pub var_offset: u16,
+    /// Storage width in bytes
+    pub size: usize,
+    pub min: u64,
+    pub max: u64,
+    pub step: u64,
+    pub default: Option<u64>,
+    /// Image offset of the default value (EFI_IFR_DEFAULT or the default option's value)
+    pub default_offset: Option<usize>,
+    pub form: String,
+    /// Enclosing suppressif/grayoutif/disableif conditions
+    pub conditions: Vec<String>,
+    /// All options in stream order, including ones wrapped in a condition inside the question
+    pub options: Vec<IfrOption>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IfrOption {
+    pub offset: usize,
+    pub text: String,
+    pub value: u64,
+    /// Flash offset and width of the option value
+    pub value_offset: usize,
+    pub value_size: usize,
+    pub flags: u8,
+    /// suppressif/grayoutif/disableif opened inside the question around this option; empty when always shown
+    pub conditions: Vec<String>,
+}
+
+enum Scope {
+    Form,
+    Question(usize),
+    Condition(usize),
+    Other,
+}
+
+fn read_le(data: &[u8], offset: usize, size: usize) -> u64 {
+    data.get(offset..offset + size)
+        .map(|b| b.iter().rev().fold(0u64, |acc, &x| (acc << 8) | x as u64))
+        .unwrap_or(0)
+}
+
+fn is_expression_op(op: u8) -> bool {
+    matches!(op, 0x12..=0x17 | 0x20..=0x22 | 0x2A..=0x59 | 0x5E | 0x60 | 0x64)
+}
+
+fn expression_token(data: &[u8], pos: usize, op: u8) -> String {
+    let word = |at: usize| read_u16(data, pos + at).unwrap_or(0);
+    match op {
+        0x12 => format!("q{:#X}=={}", word(2), word(4)),
+        0x13 => format!("q{:#X}==q{:#X}", word(2), word(4)),
+        0x14 => {
+            let count = word(4) as usize;
+            let list: Vec<String> = (0..count).map(|i| word(6 + i * 2).to_string()).collect();
+            format!("q{:#X} in [{}]", word(2), list.join(","))
+        }
+        0x15 => "and".into(),
+        0x16 => "or".into(),
+        0x17 => "not".into(),
+        0x46 => "true".into(),
+        0x47 => "false".into(),
+        _ => format!("op{:02X}", op),
+    }
+}
+
+/// Parse every IFR form package in the image and resolve its strings
+pub fn parse_form_sets(data: &[u8]) -> Vec<IfrFormSet> {
+    let packages = parse_string_packages(data, "en-US");
+    let mut form_sets = Vec::new();
+    let mut i = 0;
+    while i + 0x20 < data.len() {
+        if data[i + 3] == HII_PACKAGE_FORMS && data[i + 4] == IFR_FORM_SET
+            && (data[i + 5] & 0x7F) as usize >= 0x18 && data[i + 5] & 0x80 != 0 {
+            let length = read_u24(data, i).unwrap_or(0) as usize;
+            if length > 0x20 && i + length <= data.len() {
+                if let Some(strings) = nearest_package(&packages, i) {
+                    form_sets.push(parse_form_package(data, i, length, strings));
+                    i += length;
+                    continue;
+                }
+            }
+        }
+        i += 1;
+    }
+    form_sets
+}
+
+fn parse_form_package(data: &[u8], offset: usize, length: usize, strings: &StringPackage) -> IfrFormSet {
+    let end = offset + length;
+    let mut form_set = IfrFormSet {
+        offset,
+        guid: format_guid(&data[offset + 6..offset + 22]),
+        title: strings.get(read_u16(data, offset + 22).unwrap_or(0)),
+        varstores: Vec::new(),
+        questions: Vec::new(),
+    };
+
+    let mut scopes: Vec<Scope> = Vec::new();
+    let mut conditions: Vec<String> = Vec::new();
+    let mut forms: Vec<String> = Vec::new();
+    let mut pos = offset + 4;
+    while pos + 2 <= end {
+        let op = data[pos];
+        let len = (data[pos + 1] & 0x7F) as usize;
+        let has_scope = data[pos + 1] & 0x80 != 0;
+        if len < 2 || pos + len > end {
+            break;
+        }
+        let word = |at: usize| read_u16(data, pos + at).unwrap_or(0);
+
+        let scope = match op {
+            IFR_FORM => {
+                forms.push(strings.get(word(4)));
+                Scope::Form
+            }
+            // Fixed-offset reads below need the opcode to be long enough; short ones are false positives
+            IFR_ONE_OF | IFR_NUMERIC | IFR_CHECKBOX if len >= 14 => {
+                let (size, min, max, step) = if op == IFR_CHECKBOX {
+                    (1, 0, 1, 1)
+                } else {
+                    let size = 1usize << (data[pos + 13] & 0x03);
+                    let bound = |n: usize| if 14 + (n + 1) * size <= len { read_le(data, pos + 14 + n * size, size) } else { 0 };
+                    (size, bound(0), bound(1), bound(2))
+                };
+                form_set.questions.push(IfrQuestion {
+                    offset: pos,
+                    kind: match op {
+                        IFR_ONE_OF => "one_of",
+                        IFR_NUMERIC => "numeric",
+                        _ => "checkbox",
+                    }.to_string(),
+                    prompt: strings.get(word(2)),
+                    help: strings.get(word(4)),
+                    question_id: word(6),
+                    varstore_id: word(8),
+                    var_offset: word(10),
+                    size,
+                    min,
+                    max,
+                    step,
+                    default: None,
+                    default_offset: None,
+                    form: forms.last().cloned().unwrap_or_default(),
+                    conditions: conditions.clone(),
+                    options: Vec::new(),
+                });
+                Scope::Question(form_set.questions.len() - 1)
+            }
+            IFR_ONE_OF_OPTION if len >= 7 => {
+                let value_size = match data[pos + 5] {
+                    1 => 2,
+                    2 => 4,
+                    3 => 8,
+                    _ => 1,
+                }.min(len - 6);
+                let option = IfrOption {
+                    offset: pos,
+                    text: strings.get(word(2)),
+                    value: read_le(data, pos + 6, value_size),
+                    value_offset: pos + 6,
+                    value_size,
+                    flags: data[pos + 4],
+                    conditions: Vec::new(),
+                };
+                let mut enclosing = scopes.iter().rev().skip_while(|s| matches!(s, Scope::Condition(_)));
+                if let Some(Scope::Question(q)) = enclosing.next() {
+                    let question = &mut form_set.questions[*q];
+                    if option.flags & IFR_OPTION_DEFAULT != 0 {
+                        question.default = Some(option.value);
+                        question.default_offset = Some(option.value_offset);
+                    }
+                    let conditions = conditions.get(question.conditions.len()..).unwrap_or_default().to_vec();
+                    question.options.push(IfrOption { conditions, ..option });
+                }
+                Scope::Other
+            }
+            IFR_DEFAULT => {
+                if let Some(Scope::Question(q)) = scopes.last() {
+                    let question = &mut form_set.questions[*q];
+                    if question.default.is_none() && 5 + question.size <= len {
+                        question.default = Some(read_le(data, pos + 5, question.size));
+                        question.default_offset = Some(pos + 5);
+                    }
+                }
+                Scope::Other
+            }
+            IFR_SUPPRESS_IF | IFR_GRAY_OUT_IF | IFR_DISABLE_IF => {
+                let name = match op {
+                    IFR_SUPPRESS_IF => "suppressif",
+                    IFR_GRAY_OUT_IF => "grayoutif",
+                    _ => "disableif",
+                };
+                conditions.push(name.to_string());
+                Scope::Condition(conditions.len() - 1)
+            }
+            IFR_VARSTORE if len >= 22 => {
+                let name: String = data[pos + 22..pos + len].iter()
+                    .take_while(|&&b| b != 0).map(|&b| b as char).collect();
+                form_set.varstores.push(IfrVarStore {
+                    id: word(18),
+                    guid: format_guid(&data[pos + 2..pos + 18]),
+                    name,
+                    size: word(20),
+                });
+                Scope::Other
+            }
+            IFR_VARSTORE_EFI if len >= 26 => {
+                let name: String = data[pos + 26..pos + len].iter()
+                    .take_while(|&&b| b != 0).map(|&b| b as char).collect();
+                form_set.varstores.push(IfrVarStore {
+                    id: word(2),
+                    guid: format_guid(&data[pos + 4..pos + 20]),
+                    name,
+                    size: word(24),
+                });
+                Scope::Other
+            }
+            IFR_END => {
+                match scopes.pop() {
+                    Some(Scope::Form) => { forms.pop(); }
+                    Some(Scope::Condition(_)) => { conditions.pop(); }
+                    _ => {}
+                }
+                pos += len;
+                continue;
+            }
+            op if is_expression_op(op) => {
+                // Expression opcodes directly follow the condition that opened the scope
+                if let Some(Scope::Condition(c)) = scopes.last() {
+                    let token = expression_token(data, pos, op);
+                    conditions[*c] = format!("{} {}", conditions[*c], token);
+                }
+                Scope::Other
+            }
+            _ => Scope::Other,
+        };
+
+        if has_scope {
+            scopes.push(scope);
+        }
+        pos += len;
+    }
+    form_set
+}
+
+/// Find questions whose prompt matches `prompt` (case-insensitive)
+pub fn find_questions<'a>(form_sets: &'a [IfrFormSet], prompt: &str) -> Vec<&'a IfrQuestion> {
+    form_sets.iter()
+        .flat_map(|fs| fs.questions.iter())
+        .filter(|q| q.prompt.eq_ignore_ascii_case(prompt))
+        .collect()
+}
@@ -0,0 +1,182 @@
+//! Known-BIOS database - identifies the release of an image by hash or closest fingerprint
+
+use crate::ffs::*;
+use crate::identity::*;
+use crate::patch_file::*;
+use crate::structures::*;
+use colored::Colorize;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+
+/// Releases shipped with the analyzer (data/known_bios.json). A skeleton for now:
+/// no stock hashes and no Galileo releases, so nothing can match `exact`.
+pub const KNOWN_BIOS_DB: &str = include_str!("../data/known_bios.json");
+
+/// Scores below this are reported as unknown
+const MIN_MATCH_SCORE: u32 = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownBiosDb {
+    pub releases: Vec<KnownRelease>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownRelease {
+    pub version: String,
+    /// "Jupiter" (LCD) or "Galileo" (OLED)
+    pub platform: String,
+    /// "raw" SPI dump or "signed" capsule
+    pub kind: String,
+    pub size: usize,
+    /// SHA-256 of the stock file; null until a verified image has been fingerprinted
+    pub sha256: Option<String>,
+    /// Top-level firmware volumes as (offset, length)
+    #[serde(default)]
+    pub volumes: Vec<(usize, usize)>,
+    #[serde(default)]
+    pub notes: String,
+}
+
+/// Platform from the version prefix: F7A = Jupiter (LCD), F7G = Galileo (OLED)
+pub fn platform_of(version: &str) -> Option<&'static str> {
+    match version.get(..3) {
+        Some("F7A") => Some("Jupiter"),
+        Some("F7G") => Some("Galileo"),
+        _ => None,
+    }
+}
+
+pub fn load_known_bios() -> Result<KnownBiosDb, String> {
+    serde_json::from_str(KNOWN_BIOS_DB).map_err(|e| format!("known BIOS database: {}", e))
+}
+
+/// Fingerprint of an image in database form
+pub fn fingerprint(data: &[u8]) -> KnownRelease {
+    let version = find_bios_version(data).unwrap_or_default();
+    KnownRelease {
+        platform: platform_of(&version).unwrap_or("unknown").to_string(),
+        version,
+        kind: if data.len() > 16 * 1024 * 1024 { "signed" } else { "raw" }.to_string(),
+        size: data.len(),
+        sha256: Some(sha256_hex(data)),
+        volumes: parse_firmware_volumes(data).iter().map(|v| (v.offset, v.length)).collect(),
+        notes: String::new(),
+    }
+}
+
+/// Similarity of an image fingerprint to a known release, 0..=100. An image whose
+/// version string differs from the release's is another release, whatever its layout.
+fn score(image: &KnownRelease, known: &KnownRelease) -> u32 {
+    if !image.version.is_empty() && image.version != known.version {
+        return 0;
+    }
+    let mut score = 0;
+    if !image.version.is_empty() && image.version == known.version {
+        score += 50;
+    }
+    if image.platform == known.platform {
+        score += 10;
+    }
+    if image.size == known.size {
+        score += 15;
+    }
+    if !known.volumes.is_empty() {
+        let common = image.volumes.iter().filter(|v| known.volumes.contains(v)).count();
+        let union = image.volumes.len() + known.volumes.len() - common;
+        score += (25 * common / union.max(1)) as u32;
+    }
+    score
+}
+
+/// Match the image against the database and record the result in the report
+pub fn identify_bios(data: &[u8], report: &mut BiosReport) -> Result<(), String> {
+    println!("{}", "Identifying BIOS release...".dimmed());
+    let db = load_known_bios()?;
+    let image = fingerprint(data);
+    let sha256 = image.sha256.clone().unwrap_or_default();
+
+    let exact = db.releases.iter().find(|r| r.sha256.as_ref().is_some_and(|h| h.eq_ignore_ascii_case(&sha256)));
+    let best = db.releases.iter().map(|r| (score(&image, r), r)).max_by_key(|(s, _)| *s);
+
+    let identification = match (exact, best) {
+        (Some(release), _) => BiosIdentification {
+            sha256,
+            version: Some(release.version.clone()),
+            platform: Some(release.platform.clone()),
+            kind: release.kind.clone(),
+            match_type: "exact".to_string(),
+            score: 100,
+            notes: release.notes.clone(),
+        },
+        (None, Some((score, release))) if score >= MIN_MATCH_SCORE => BiosIdentification {
+            sha256,
+            version: Some(release.version.clone()),
+            platform: Some(release.platform.clone()),
+            kind: release.kind.clone(),
+            // Without a stock hash in the database the image cannot be told apart from a modified one
+            match_type: if release.sha256.is_some() { "modified" } else { "unverified" }.to_string(),
+            score,
+            notes: release.notes.clone(),
+        },
+        _ => BiosIdentification {
+            sha256,
+            version: (!image.version.is_empty()).then(|| image.version.clone()),
+            platform: platform_of(&image.version).map(String::from),
+            kind: image.kind.clone(),
+            match_type: "unknown".to_string(),
+            score: 0,
+            notes: String::new(),
+        },
+    };
+
+    println!("  {} {} ({}, score {})", identification.version.as_deref().unwrap_or("unknown"),
+        identification.platform.as_deref().unwrap_or(""), identification.match_type, identification.score);
+    report.identification = Some(identification);
+    Ok(())
+}
+
+/// `fingerprint <image>` - print a database entry for a verified stock image
+pub fn run_fingerprint(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let image = args.first().ok_or("usage: bios_analyzer fingerprint <image>")?;
+    let file = File::open(image)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut entry = fingerprint(&mmap[..]);
+    entry.notes = image.clone();
+    println!("{}", serde_json::to_string_pretty(&entry)?);
+    eprintln!("{}", "Add this entry to data/known_bios.json only for an unmodified Valve image".yellow());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(version: &str, size: usize) -> KnownRelease {
+        KnownRelease {
+            version: version.to_string(),
+            platform: platform_of(version).unwrap_or("unknown").to_string(),
+            kind: "signed".to_string(),
+            size,
+            sha256: None,
+            volumes: vec![(0x100, 0x1000)],
+            notes: String::new(),
+        }
+    }
+
+    #[test]
+    fn score_requires_the_same_version() {
+        let known = release("F7A0133", 17_778_888);
+        assert_eq!(score(&release("F7A0133", 17_778_888), &known), 100);
+        assert_eq!(score(&release("F7A0134", 17_778_888), &known), 0);
+        assert_eq!(score(&release("F7G0110", 17_778_888), &known), 0);
+        // No version string: platform is unknown too, size and layout still count
+        assert_eq!(score(&release("", 17_778_888), &known), 15 + 25);
+    }
+
+    #[test]
+    fn bundled_database_loads() {
+        let db = load_known_bios().unwrap();
+        assert!(db.releases.iter().all(|r| platform_of(&r.version) == Some(r.platform.as_str())));
+    }
+}
@@ -15,6 +15,34 @@ mod extreme_analysis;
 mod dpm_analysis;
 mod hidden_menu;
 mod ifr_parser;
+mod ifr_insert;
+mod ifr_diff;
+mod ffs;
+mod apcb;
+mod hii;
+mod memclk;
+mod voltage;
+mod spd;
+mod timings;
+mod checksums;
+mod confidence;
+mod entropy;
+mod psp;
+mod regions;
+mod nvram;
+mod power_limits;
+mod validate;
+mod check;
+mod diff;
+mod known_bios;
+mod identity;
+mod mirror;
+mod patch_engine;
+mod patch_file;
+mod recipes;
+mod profile;
+#[cfg(test)]
+mod test_image;
 
 use structures::*;
 use analysis::*;
@@ -25,11 +53,58 @@ use extreme_analysis::*;
 use dpm_analysis::*;
 use hidden_menu::*;
 use ifr_parser::*;
+use ifr_insert::*;
+use ifr_diff::*;
+use mirror::*;
+use check::*;
+use diff::*;
+use confidence::*;
+use entropy::*;
+use known_bios::*;
+use identity::*;
+use patch_engine::*;
+use patch_file::*;
+use power_limits::*;
+use recipes::*;
+use profile::*;
+use timings::*;
+use voltage::*;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
-    let filename = args.get(1).map(|s| s.as_str()).unwrap_or("F7A0133_sign.fd");
-    
+    match args.get(1).map(|s| s.as_str()) {
+        Some("patch") => run_patch(&args[2..]),
+        Some("apply-diff") => run_apply_diff(&args[2..]),
+        Some("check") => run_check(&args[2..]),
+        Some("fingerprint") => run_fingerprint(&args[2..]),
+        Some("timings") => run_timings(&args[2..]),
+        Some("add-options") => run_add_options(&args[2..]),
+        Some("profile") => run_profile(&args[2..]),
+        Some("entropy") => run_entropy(&args[2..]),
+        Some("diff") => run_diff(&args[2..]),
+        Some("ifr-diff") => run_ifr_diff(&args[2..]),
+        other => run_analysis(other.unwrap_or("F7A0133_sign.fd"), args.get(2..).unwrap_or(&[])),
+    }
+}
+
+/// `bios_analyzer [image] [--min-confidence 0..100] [--all-regions]`
+fn run_analysis(filename: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer [image] [--min-confidence <0..100>] [--all-regions]";
+    let mut min_confidence = 0;
+    let mut all_regions = false;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--min-confidence" => {
+                let v = rest.next().ok_or(usage)?;
+                min_confidence = v.parse::<u32>().ok().filter(|&c| c <= 100)
+                    .ok_or_else(|| format!("bad confidence '{}': expected 0..100", v))?;
+            }
+            "--all-regions" => all_regions = true,
+            _ => return Err(usage.into()),
+        }
+    }
+
     println!("{}", "═".repeat(80).cyan());
     println!("{}", " STEAM DECK BIOS DEEP ANALYZER v0.1".bold().cyan());
     println!("{}", "═".repeat(80).cyan());
@@ -43,17 +118,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     let mut report = BiosReport::new(filename, data.len());
     
+    // 0. Identity and known-BIOS identification
+    analyze_identity(data, &mut report);
+    identify_bios(data, &mut report)?;
+    
     // 1. UEFI Volume Analysis
     analyze_uefi_volumes(data, &mut report);
+
+    // 1a. Region classification; table heuristics only report hits in data regions
+    let scoring = ScoringContext::new(data, min_confidence, all_regions);
+    report.regions = scoring.regions.regions();
+    report.entropy = scoring.regions.entropy.clone();
     
     // 2. SPD Structures
     analyze_spd_structures(data, &mut report);
     
     // 3. Frequency Tables
     analyze_frequency_tables(data, &mut report);
+
+    // 3a. Memory voltage indices (IFR)
+    analyze_voltage_settings(data, &mut report);
     
     // 4. Power Management
     analyze_power_management(data, &mut report);
+    analyze_power_limits(data, &mut report);
     
     // 5. SMU Firmware
     analyze_smu(data, &mut report);
@@ -65,7 +153,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     analyze_guids(data, &mut report);
     
     // 8. Numeric Tables
-    analyze_numeric_tables(data, &mut report);
+    analyze_numeric_tables(data, &scoring, &mut report);
     
     // 9. AMD/PSP Structures
     analyze_amd_psp(data, &mut report);
@@ -74,14 +162,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     analyze_ec(data, &mut report);
     
     // 11. Deep Analysis (GPU, Voltages, Timings, etc.)
-    let deep_report = deep_analyze(data);
+    let deep_report = deep_analyze(data, &scoring);
     deep_report.print();
     
     // 12. Advanced Analysis (Hidden features, SMU commands, etc.)
     find_hidden_features(data);
     
     // 13. Ultra Deep Analysis (H2O unlock, UMC, Fan curves, Thermal, SMU IDs)
-    ultra_deep_analysis(data);
+    ultra_deep_analysis(data, &scoring, &report.power_limits);
     
     // 14. Extreme Analysis (CBS/PBS, STAPM, Voltages, Clock domains)
     extreme_analysis(data);
@@ -95,6 +183,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 17. IFR Parser - Hidden Options
     parse_ifr_options(data);
     
+    // 18. Mirror (A/B) regions
+    analyze_mirrors(data, &mut report);
+    
+    // 19. Patch candidates from recipes
+    resolve_recipes(data, &load_recipes(None)?, &mut report)?;
+    
     // Print Report
     report.print();
     
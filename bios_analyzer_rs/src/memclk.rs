@@ -0,0 +1,77 @@
+//! Memory clock codes - AMD MEMCLK code to MHz / MT/s, IFR exposure
+
+use crate::ifr_parser::*;
+
+/// IFR question that selects the memory clock code
+pub const MEMORY_CLOCK_QUESTION: &str = "Memory Clock Speed";
+/// Codes from here up step by 50 MHz from 2500 MHz; below it by 33.3 MHz
+const MEMCLK_50MHZ_BASE: u16 = 0x4B;
+/// Table entry past the last clock code, meaning not documented
+const MEMCLK_SPECIAL: u16 = 0x80;
+/// ONE_OF value meaning "Auto" (SPD speed)
+const MEMCLK_AUTO: u16 = 0xFF;
+
+/// Memory clock in MHz (half the data rate) for a clock code, as the CBS menu labels it
+pub fn memclk_mhz(code: u16) -> Option<u32> {
+    match code {
+        0 | MEMCLK_SPECIAL.. => None,
+        c if c >= MEMCLK_50MHZ_BASE => Some(2500 + (c - MEMCLK_50MHZ_BASE) as u32 * 50),
+        c => Some((c as u32 * 100 + 1) / 3),
+    }
+}
+
+pub fn memclk_note(code: u16) -> &'static str {
+    match code {
+        MEMCLK_SPECIAL => "special (Extreme/Auto?)",
+        MEMCLK_AUTO => "Auto",
+        _ if memclk_mhz(code).is_none() => "unknown",
+        _ => "",
+    }
+}
+
+/// Clock codes offered by every "Memory Clock Speed" question in the image; options under
+/// a suppressif inside the question are not offered
+pub fn exposed_memclk_codes(data: &[u8]) -> Vec<u16> {
+    let form_sets = parse_form_sets(data);
+    let mut codes: Vec<u16> = find_questions(&form_sets, MEMORY_CLOCK_QUESTION).iter()
+        .flat_map(|q| q.options.iter())
+        .filter(|o| !o.conditions.iter().any(|c| c.starts_with("suppressif")))
+        .map(|o| o.value as u16)
+        .collect();
+    codes.sort_unstable();
+    codes.dedup();
+    codes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CBS menu options of the stock BIOS, "Option: 667MHz, Value: 0x14 {...}"
+    const MENU: &str = include_str!("../../base/memory-clock.txt");
+
+    #[test]
+    fn memclk_mhz_matches_menu_labels() {
+        let mut checked = 0;
+        for line in MENU.lines() {
+            let Some(option) = line.split("Option: ").nth(1) else { continue };
+            let (label, rest) = option.split_once(", Value: 0x").unwrap();
+            let code = u16::from_str_radix(&rest[..2], 16).unwrap();
+            match label.to_ascii_uppercase().strip_suffix("MHZ") {
+                Some(mhz) => assert_eq!(memclk_mhz(code), Some(mhz.parse().unwrap()), "{}", line),
+                None => assert_eq!(memclk_mhz(code), None, "{}", line),
+            }
+            checked += 1;
+        }
+        assert!(checked > 40);
+    }
+
+    #[test]
+    fn memclk_codes_above_the_menu() {
+        assert_eq!(memclk_mhz(0x5A), Some(3250));
+        assert_eq!(memclk_mhz(0x5F), Some(3500));
+        assert_eq!(memclk_mhz(0x7F), Some(5100));
+        assert_eq!(memclk_mhz(MEMCLK_SPECIAL), None);
+        assert_eq!(memclk_note(MEMCLK_AUTO), "Auto");
+    }
+}
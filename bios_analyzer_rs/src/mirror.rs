@@ -0,0 +1,176 @@
+//! Mirror detection - A/B (primary/recovery) copies, divergences, lockstep patching
+
+use crate::ffs::*;
+use crate::structures::*;
+use colored::Colorize;
+
+const BLOCK: usize = 0x1000;
+/// Share of equal bytes for two blocks to count as copies
+const BLOCK_MATCH_PERCENT: usize = 90;
+/// Fewest non-blank matching blocks for a range to count as a mirror
+const MIN_MIRROR_BLOCKS: usize = 4;
+/// Equal bytes shorter than this between two differences are merged into one divergence
+const MERGE_GAP: usize = 8;
+
+/// Offset deltas between firmware volumes that appear twice in the image
+fn candidate_deltas(data: &[u8]) -> Vec<usize> {
+    let volumes = parse_firmware_volumes(data);
+    let mut deltas = Vec::new();
+    for (i, a) in volumes.iter().enumerate() {
+        for b in &volumes[i + 1..] {
+            if a.length == b.length && a.guid == b.guid && a.name == b.name
+                && data[a.offset..a.offset + a.header_length] == data[b.offset..b.offset + b.header_length] {
+                let delta = b.offset - a.offset;
+                if !deltas.contains(&delta) {
+                    deltas.push(delta);
+                }
+            }
+        }
+    }
+    deltas
+}
+
+fn is_blank(block: &[u8]) -> bool {
+    block.iter().all(|&b| b == 0xFF) || block.iter().all(|&b| b == 0x00)
+}
+
+fn divergences(data: &[u8], start: usize, length: usize, delta: usize) -> Vec<MirrorDivergence> {
+    let mut out = Vec::new();
+    let mut i = start;
+    let end = start + length;
+    while i < end {
+        if data[i] == data[i + delta] {
+            i += 1;
+            continue;
+        }
+        let run_start = i;
+        let mut run_end = i + 1;
+        let mut j = run_end;
+        while j < end && j - run_end <= MERGE_GAP {
+            if data[j] != data[j + delta] {
+                run_end = j + 1;
+            }
+            j += 1;
+        }
+        out.push(MirrorDivergence {
+            offset: run_start as u64,
+            mirror_offset: (run_start + delta) as u64,
+            length: (run_end - run_start) as u64,
+        });
+        i = run_end;
+    }
+    out
+}
+
+/// Find ranges duplicated at a fixed delta. Deltas come from firmware volumes
+/// found twice; the ranges are then grown block by block around them.
+pub fn find_mirror_regions(data: &[u8]) -> Vec<MirrorRegion> {
+    let mut regions = Vec::new();
+    for delta in candidate_deltas(data) {
+        let blocks = (data.len() - delta) / BLOCK;
+        let mut b = 0;
+        while b < blocks {
+            // Runs start and end on blocks with real content; blank blocks only extend them
+            let mut matched = 0;
+            let mut last_match = None;
+            let mut e = b;
+            while e < blocks && (e - b + 1) * BLOCK <= delta {
+                let x = e * BLOCK;
+                let primary = &data[x..x + BLOCK];
+                let mirror = &data[x + delta..x + delta + BLOCK];
+                if is_blank(primary) && primary == mirror {
+                    e += 1;
+                    continue;
+                }
+                let equal = primary.iter().zip(mirror).filter(|(p, m)| p == m).count();
+                if equal * 100 < BLOCK * BLOCK_MATCH_PERCENT {
+                    break;
+                }
+                if last_match.is_none() {
+                    b = e;
+                }
+                matched += 1;
+                last_match = Some(e);
+                e += 1;
+            }
+
+            match last_match {
+                Some(last) if matched >= MIN_MIRROR_BLOCKS => {
+                    let start = b * BLOCK;
+                    let length = (last + 1 - b) * BLOCK;
+                    regions.push(MirrorRegion {
+                        primary: start as u64,
+                        mirror: (start + delta) as u64,
+                        length: length as u64,
+                        delta: delta as u64,
+                        divergences: divergences(data, start, length, delta),
+                    });
+                    b = last + 1;
+                }
+                _ => b = e.max(b) + 1,
+            }
+        }
+    }
+    regions
+}
+
+pub fn analyze_mirrors(data: &[u8], report: &mut BiosReport) {
+    println!("{}", "Analyzing mirror regions...".dimmed());
+    report.mirrors = find_mirror_regions(data);
+    for region in &report.mirrors {
+        for d in region.divergences.iter().take(20) {
+            println!("  {} 0x{:08X} / 0x{:08X} ({} bytes): {} / {}", "diverged".red(),
+                d.offset, d.mirror_offset, d.length,
+                hex::encode(&data[d.offset as usize..(d.offset + d.length.min(16)) as usize]),
+                hex::encode(&data[d.mirror_offset as usize..(d.mirror_offset + d.length.min(16)) as usize]));
+        }
+    }
+}
+
+/// Counterpart of `[offset, offset + len)` in the other copy, if it lies in a mirrored range
+pub fn mirror_offset(regions: &[MirrorRegion], offset: u64, len: u64) -> Option<u64> {
+    regions.iter().find_map(|r| {
+        if offset >= r.primary && offset + len <= r.primary + r.length {
+            Some(offset + r.delta)
+        } else if offset >= r.mirror && offset + len <= r.mirror + r.length {
+            Some(offset - r.delta)
+        } else {
+            None
+        }
+    })
+}
+
+/// Candidates that apply `patches` to the other copy as well.
+/// Copies that already carry the patch are skipped; a copy holding different bytes is an error,
+/// since patching only one side would leave the A/B images out of sync.
+pub fn mirror_patches(data: &[u8], regions: &[MirrorRegion], patches: &[&PatchCandidate])
+    -> Result<Vec<PatchCandidate>, String> {
+    let mut mirrored = Vec::new();
+    for patch in patches {
+        let len = patch.original.len() as u64;
+        let Some(target) = mirror_offset(regions, patch.offset, len) else {
+            continue;
+        };
+        if patches.iter().any(|p| p.offset == target && p.patched == patch.patched) {
+            continue;
+        }
+        let current = &data[target as usize..(target + len) as usize];
+        if current == &patch.original[..] {
+            println!("    {} 0x{:08X} -> 0x{:08X} [{}]", "mirror".cyan(), patch.offset, target, patch.recipe);
+            mirrored.push(PatchCandidate {
+                recipe: patch.recipe.clone(),
+                offset: target,
+                original: patch.original.clone(),
+                patched: patch.patched.clone(),
+                description: format!("{} (mirror)", patch.description),
+                effect: patch.effect.clone(),
+                risk: patch.risk.clone(),
+            });
+        } else if current != &patch.patched[..] {
+            return Err(format!("mirror of 0x{:08X} at 0x{:08X} holds {}, expected {} [{}]; \
+                the copies diverge, use --no-mirror to patch one copy only",
+                patch.offset, target, hex::encode(current), hex::encode(&patch.original), patch.recipe));
+        }
+    }
+    Ok(mirrored)
+}
@@ -0,0 +1,98 @@
+//! NVRAM parser - EFI variable stores (normal and authenticated)
+
+use crate::analysis::*;
+use serde::Serialize;
+
+const VARIABLE_STORE_GUID: &str = "DDCF3616-3275-4164-98B6-FE85707FFE7D";
+const AUTH_VARIABLE_STORE_GUID: &str = "AAF32C78-947B-439A-A180-2E144EC37792";
+const VARIABLE_STORE_FORMATTED: u8 = 0x5A;
+const VARIABLE_STORE_HEALTHY: u8 = 0xFE;
+const VARIABLE_STORE_HEADER_SIZE: usize = 28;
+
+const VARIABLE_START_ID: u16 = 0x55AA;
+/// Variable state after a completed write (bits are cleared as the state advances)
+pub const VAR_ADDED: u8 = 0x3F;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VariableStore {
+    pub offset: usize,
+    pub size: usize,
+    pub authenticated: bool,
+    pub variables: Vec<NvramVariable>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NvramVariable {
+    pub offset: usize,
+    pub name: String,
+    pub guid: String,
+    pub attributes: u32,
+    pub state: u8,
+    pub data_offset: usize,
+    pub data_size: usize,
+}
+
+impl NvramVariable {
+    pub fn is_active(&self) -> bool {
+        self.state == VAR_ADDED
+    }
+}
+
+/// Find all variable stores by their header GUID
+pub fn parse_variable_stores(data: &[u8]) -> Vec<VariableStore> {
+    let mut stores = Vec::new();
+    for offset in 0..data.len().saturating_sub(VARIABLE_STORE_HEADER_SIZE) {
+        // Cheap prefilter on the format/state bytes before formatting the GUID
+        if data[offset + 20] != VARIABLE_STORE_FORMATTED || data[offset + 21] != VARIABLE_STORE_HEALTHY {
+            continue;
+        }
+        let guid = format_guid(&data[offset..offset + 16]);
+        let authenticated = guid == AUTH_VARIABLE_STORE_GUID;
+        if !authenticated && guid != VARIABLE_STORE_GUID {
+            continue;
+        }
+        let size = read_u32(data, offset + 16).unwrap_or(0) as usize;
+        if size <= VARIABLE_STORE_HEADER_SIZE || offset + size > data.len() {
+            continue;
+        }
+        let variables = parse_variables(data, offset + VARIABLE_STORE_HEADER_SIZE, offset + size, authenticated);
+        stores.push(VariableStore { offset, size, authenticated, variables });
+    }
+    stores
+}
+
+fn parse_variables(data: &[u8], start: usize, end: usize, authenticated: bool) -> Vec<NvramVariable> {
+    let header_size = if authenticated { 60 } else { 32 };
+    let mut variables = Vec::new();
+    let mut pos = (start + 3) & !3;
+    while pos + header_size <= end && read_u16(data, pos) == Some(VARIABLE_START_ID) {
+        let state = data[pos + 2];
+        let attributes = read_u32(data, pos + 4).unwrap_or(0);
+        let sizes = pos + header_size - 24;
+        let name_size = read_u32(data, sizes).unwrap_or(0) as usize;
+        let data_size = read_u32(data, sizes + 4).unwrap_or(0) as usize;
+        let guid = format_guid(&data[sizes + 8..sizes + 24]);
+
+        let name_offset = pos + header_size;
+        let data_offset = name_offset + name_size;
+        if data_offset + data_size > end {
+            break;
+        }
+        let units: Vec<u16> = data[name_offset..data_offset].chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+
+        variables.push(NvramVariable {
+            offset: pos,
+            name: String::from_utf16_lossy(&units),
+            guid,
+            attributes,
+            state,
+            data_offset,
+            data_size,
+        });
+        pos = (data_offset + data_size + 3) & !3;
+    }
+    variables
+}
@@ -0,0 +1,409 @@
+//! Patch engine - applies PatchCandidate entries to a copy of the image
+
+use crate::checksums::*;
+use crate::mirror::*;
+use crate::patch_file::*;
+use crate::recipes::*;
+use crate::validate::*;
+use crate::structures::*;
+use colored::Colorize;
+use memmap2::Mmap;
+use std::fs::File;
+
+/// Risk levels in ascending order
+pub const RISK_LEVELS: &[&str] = &["low", "medium", "high"];
+
+pub fn risk_rank(risk: &str) -> usize {
+    RISK_LEVELS.iter().position(|&r| r == risk).unwrap_or(RISK_LEVELS.len())
+}
+
+/// Verify every candidate against the image, then apply them to a copy.
+/// Nothing is written if any `original` does not match.
+pub fn apply_patches(data: &[u8], patches: &[&PatchCandidate]) -> Result<(Vec<u8>, Vec<AppliedPatch>), String> {
+    let mut mismatches = Vec::new();
+    for patch in patches {
+        let start = patch.offset as usize;
+        let end = start + patch.original.len();
+        if patch.original.len() != patch.patched.len() {
+            mismatches.push(format!("0x{:08X} {}: original/patched length differ ({} vs {})",
+                patch.offset, patch.description, patch.original.len(), patch.patched.len()));
+        } else if end > data.len() {
+            mismatches.push(format!("0x{:08X} {}: beyond end of image", patch.offset, patch.description));
+        } else if data[start..end] != patch.original[..] {
+            mismatches.push(format!("0x{:08X} {}: expected {} found {}",
+                patch.offset, patch.description,
+                hex::encode(&patch.original), hex::encode(&data[start..end])));
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(format!("refusing to patch, {} mismatch(es):\n  {}",
+            mismatches.len(), mismatches.join("\n  ")));
+    }
+
+    let mut out = data.to_vec();
+    let mut applied = Vec::new();
+    for patch in patches {
+        let start = patch.offset as usize;
+        out[start..start + patch.patched.len()].copy_from_slice(&patch.patched);
+        applied.push(AppliedPatch {
+            offset: patch.offset,
+            description: patch.description.clone(),
+            risk: patch.risk.clone(),
+            original: hex::encode(&patch.original),
+            patched: hex::encode(&patch.patched),
+        });
+    }
+    Ok((out, applied))
+}
+
+/// Check the selection for duplicate targets, overlapping byte ranges and
+/// mutually exclusive recipes. Exact duplicates are dropped; anything else is an error.
+pub fn check_conflicts<'a>(patches: &[&'a PatchCandidate], recipes: &[PatchRecipe])
+    -> Result<Vec<&'a PatchCandidate>, String> {
+    let mut kept: Vec<&PatchCandidate> = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for &patch in patches {
+        let start = patch.offset;
+        let end = start + patch.original.len() as u64;
+        let mut duplicate = false;
+        for other in &kept {
+            let other_end = other.offset + other.original.len() as u64;
+            if start >= other_end || other.offset >= end {
+                continue;
+            }
+            if other.offset == start && other.original == patch.original && other.patched == patch.patched {
+                println!("    {} 0x{:08X}: [{}] duplicates [{}], applied once",
+                    "duplicate".yellow(), start, patch.recipe, other.recipe);
+                duplicate = true;
+            } else {
+                conflicts.push(format!("0x{:08X}..0x{:08X} [{}] overlaps 0x{:08X}..0x{:08X} [{}]",
+                    start, end, patch.recipe, other.offset, other_end, other.recipe));
+            }
+        }
+        if !duplicate {
+            kept.push(patch);
+        }
+    }
+
+    let selected: Vec<&str> = kept.iter().map(|p| p.recipe.as_str()).collect();
+    for recipe in recipes.iter().filter(|r| selected.contains(&r.name.as_str())) {
+        for other in &recipe.conflicts_with {
+            // Report each pair once, even when both sides declare it
+            let declared_both = recipes.iter().any(|r| &r.name == other && r.conflicts_with.contains(&recipe.name));
+            if selected.contains(&other.as_str()) && (!declared_both || recipe.name < *other) {
+                conflicts.push(format!("recipes [{}] and [{}] are mutually exclusive", recipe.name, other));
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(format!("refusing to patch, {} conflict(s):\n  {}",
+            conflicts.len(), conflicts.join("\n  ")));
+    }
+    Ok(kept)
+}
+
+/// Parse a comma separated list of candidate indices
+fn parse_selection(list: &str) -> Result<Vec<usize>, String> {
+    list.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| s.trim().parse::<usize>().map_err(|e| format!("bad index '{}': {}", s, e)))
+        .collect()
+}
+
+/// `patch <input> <output> [--recipes file.json] [--select 0,2,...] [--risk low|medium|high] [--no-mirror]`
+pub fn run_patch(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer patch <input> <output> [--recipes file.json] [--select 0,2,...] [--risk low|medium|high] [--no-mirror]";
+    let input = args.first().ok_or(usage)?;
+    let output = args.get(1).ok_or(usage)?;
+
+    let mut selection: Option<Vec<usize>> = None;
+    let mut max_risk = "low".to_string();
+    let mut recipe_path: Option<String> = None;
+    let mut sync_mirrors = true;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--recipes" => {
+                recipe_path = Some(args.get(i + 1).ok_or(usage)?.clone());
+                i += 2;
+            }
+            "--select" => {
+                selection = Some(parse_selection(args.get(i + 1).ok_or(usage)?)?);
+                i += 2;
+            }
+            "--risk" => {
+                max_risk = args.get(i + 1).ok_or(usage)?.clone();
+                if risk_rank(&max_risk) >= RISK_LEVELS.len() {
+                    return Err(format!("unknown risk level '{}'", max_risk).into());
+                }
+                i += 2;
+            }
+            "--no-mirror" => {
+                sync_mirrors = false;
+                i += 1;
+            }
+            other => return Err(format!("unknown option '{}'\n{}", other, usage).into()),
+        }
+    }
+
+    println!("{}", "═".repeat(80).cyan());
+    println!("{}", " PATCH ENGINE".bold().cyan());
+    println!("{}", "═".repeat(80).cyan());
+
+    let file = File::open(input)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let data = &mmap[..];
+
+    let recipes = load_recipes(recipe_path.as_deref())?;
+    let mut report = BiosReport::new(input, data.len());
+    resolve_recipes(data, &recipes, &mut report)?;
+
+    println!("\n{}", "  Candidates:".bold());
+    for (idx, patch) in report.patches.iter().enumerate() {
+        println!("    [{:2}] {:6} @ 0x{:08X}: {} [{}] ({} -> {})", idx, patch.risk, patch.offset,
+            patch.description, patch.recipe, hex::encode(&patch.original), hex::encode(&patch.patched));
+    }
+
+    let selected: Vec<&PatchCandidate> = match &selection {
+        Some(indices) => indices.iter()
+            .map(|&idx| report.patches.get(idx).ok_or(format!("no candidate with index {}", idx)))
+            .collect::<Result<_, _>>()?,
+        None => report.patches.iter()
+            .filter(|p| risk_rank(&p.risk) <= risk_rank(&max_risk))
+            .collect(),
+    };
+
+    if selected.is_empty() {
+        return Err("no patch candidates selected".into());
+    }
+
+    let mirrored = if sync_mirrors {
+        println!("\n{}", "  Synchronizing mirrors...".bold());
+        mirror_patches(data, &find_mirror_regions(data), &selected)?
+    } else {
+        Vec::new()
+    };
+    let mut selected = selected;
+    selected.extend(mirrored.iter());
+
+    println!("\n{}", "  Checking conflicts...".bold());
+    let selected = check_conflicts(&selected, &recipes)?;
+
+    let (mut patched, applied) = apply_patches(data, &selected)?;
+
+    println!("\n{}", "  Fixing checksums...".bold());
+    let changed: Vec<(usize, usize)> = selected.iter()
+        .map(|p| (p.offset as usize, p.patched.len()))
+        .collect();
+    let checksums = fix_checksums(data, &mut patched, &changed);
+    save_patched(input, output, data, &patched, applied, checksums)?;
+
+    Ok(())
+}
+
+/// Re-validate the patched image, write it and save `<output>.patch.json` and `<output>.diff.json`
+pub fn save_patched(input: &str, output: &str, data: &[u8], patched: &[u8], applied: Vec<AppliedPatch>,
+    checksums: Vec<ChecksumFix>) -> Result<(), Box<dyn std::error::Error>> {
+    for fix in &checksums {
+        println!("    {} @ 0x{:08X} {}: {} -> {}", fix.kind, fix.offset, fix.name, fix.old, fix.new);
+    }
+    let bytes_changed = data.iter().zip(patched).filter(|(a, b)| a != b).count();
+
+    // Validate before writing, so a broken image never lands under the output name
+    println!("\n{}", "  Re-validating output...".bold());
+    let allowed: Vec<(usize, usize)> = applied.iter()
+        .map(|p| (p.offset as usize, p.patched.len() / 2))
+        .chain(checksums.iter().map(|c| (c.offset as usize, c.new.len() / 2)))
+        .collect();
+    validate_patched(data, patched, &allowed)?;
+    std::fs::write(output, patched)?;
+
+    println!("\n{}", "  Applied:".bold());
+    for patch in &applied {
+        if patch.patched.len() > 64 {
+            println!("    @ 0x{:08X}: {} ({} bytes)", patch.offset, patch.description.green(), patch.patched.len() / 2);
+        } else {
+            println!("    @ 0x{:08X}: {} {} -> {}", patch.offset, patch.description.green(),
+                patch.original, patch.patched);
+        }
+    }
+    println!("    {} patches, {} bytes changed", applied.len(), bytes_changed);
+
+    let summary = PatchSummary {
+        input: input.to_string(),
+        output: output.to_string(),
+        applied,
+        checksums,
+        bytes_changed,
+    };
+    let summary_path = format!("{}.patch.json", output);
+    std::fs::write(&summary_path, serde_json::to_string_pretty(&summary)?)?;
+
+    let description = summary.applied.iter().map(|p| p.description.as_str()).collect::<Vec<_>>().join(", ");
+    let diff = make_patch_file(data, patched, &description)?;
+    let diff_path = format!("{}.diff.json", output);
+    std::fs::write(&diff_path, serde_json::to_string_pretty(&diff)?)?;
+
+    println!("\n{}", format!("Patched image saved to {}, summary to {}", output, summary_path).green());
+    println!("{}", format!("Reversible diff saved to {} (source SHA-256 {})", diff_path, diff.source_sha256).green());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    fn candidate(recipe: &str, offset: u64, original: &[u8], patched: &[u8]) -> PatchCandidate {
+        PatchCandidate {
+            recipe: recipe.to_string(),
+            offset,
+            original: original.to_vec(),
+            patched: patched.to_vec(),
+            description: format!("{} @ {}", recipe, offset),
+            effect: String::new(),
+            risk: "low".to_string(),
+        }
+    }
+
+    fn recipe(name: &str, pattern: &str, replacement: &str, conflicts_with: &[&str]) -> PatchRecipe {
+        PatchRecipe {
+            name: name.to_string(),
+            description: name.to_string(),
+            effect: String::new(),
+            risk: "low".to_string(),
+            locator: Locator::Pattern { pattern: pattern.to_string(), offset: 0 },
+            expected: pattern.to_string(),
+            replacement: replacement.to_string(),
+            versions: Vec::new(),
+            conflicts_with: conflicts_with.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Volume with one file holding a RAW section with "0123456789"
+    fn image() -> Vec<u8> {
+        volume(&[ffs_file(0x22, &section(SECTION_RAW, b"0123456789"))], 0x1000)
+    }
+
+    /// Write `image` and `recipes` to temp files and run `patch` on them with `extra` arguments
+    fn run(name: &str, recipes: Vec<PatchRecipe>, extra: &[&str]) -> (Result<(), String>, String) {
+        let input = temp_path(&format!("{}.bin", name));
+        let output = temp_path(&format!("{}.out.bin", name));
+        let recipe_path = temp_path(&format!("{}.json", name));
+        std::fs::write(&input, image()).unwrap();
+        std::fs::write(&recipe_path, serde_json::to_string(&RecipeFile { recipes }).unwrap()).unwrap();
+        let mut args: Vec<String> = vec![input, output.clone(), "--recipes".to_string(), recipe_path];
+        args.extend(extra.iter().map(|s| s.to_string()));
+        let result = run_patch(&args).map_err(|e| e.to_string());
+        for path in [&args[0], &args[3]] {
+            let _ = std::fs::remove_file(path);
+        }
+        (result, output)
+    }
+
+    /// Read and remove the patched image and the files saved next to it
+    fn take_outputs(output: &str) -> (Vec<u8>, String, bool) {
+        let image = std::fs::read(output).unwrap();
+        let summary = std::fs::read_to_string(format!("{}.patch.json", output)).unwrap();
+        let diff = std::path::Path::new(&format!("{}.diff.json", output)).exists();
+        for path in [output.to_string(), format!("{}.patch.json", output), format!("{}.diff.json", output)] {
+            let _ = std::fs::remove_file(path);
+        }
+        (image, summary, diff)
+    }
+
+    #[test]
+    fn apply_patches_refuses_any_original_mismatch() {
+        let data = b"0123456789".to_vec();
+        let good = candidate("good", 1, b"1", b"A");
+        let stale = candidate("stale", 4, b"X", b"B");
+        let short = candidate("short", 6, b"67", b"C");
+        let past = candidate("past", 9, b"9X", b"DD");
+        let err = apply_patches(&data, &[&good, &stale, &short, &past]).unwrap_err();
+        assert!(err.starts_with("refusing to patch, 3 mismatch(es)"), "{}", err);
+        assert!(err.contains("expected 58 found 34"), "{}", err);
+        assert!(err.contains("length differ (2 vs 1)"), "{}", err);
+        assert!(err.contains("beyond end of image"), "{}", err);
+    }
+
+    #[test]
+    fn apply_patches_applies_only_the_selected_candidates() {
+        let data = b"0123456789".to_vec();
+        let candidates = [candidate("a", 1, b"1", b"A"), candidate("b", 4, b"45", b"BC"), candidate("c", 8, b"8", b"D")];
+        let (patched, applied) = apply_patches(&data, &[&candidates[0], &candidates[2]]).unwrap();
+        assert_eq!(patched, b"0A234567D9");
+        let applied: Vec<(u64, &str, &str)> = applied.iter()
+            .map(|p| (p.offset, p.original.as_str(), p.patched.as_str()))
+            .collect();
+        assert_eq!(applied, [(1, "31", "41"), (8, "38", "44")]);
+    }
+
+    #[test]
+    fn check_conflicts_drops_exact_duplicates() {
+        let a = candidate("a", 4, b"45", b"AB");
+        let b = candidate("b", 4, b"45", b"AB");
+        let kept = check_conflicts(&[&a, &b], &[]).unwrap();
+        assert_eq!(kept.iter().map(|p| p.recipe.as_str()).collect::<Vec<_>>(), ["a"]);
+    }
+
+    #[test]
+    fn check_conflicts_refuses_overlapping_ranges() {
+        // Same target with other bytes, and a range that only partly overlaps
+        let a = candidate("a", 4, b"45", b"AB");
+        let b = candidate("b", 4, b"45", b"CD");
+        let c = candidate("c", 5, b"56", b"EF");
+        let d = candidate("d", 6, b"6", b"G");
+        let err = check_conflicts(&[&a, &b, &c, &d], &[]).unwrap_err();
+        assert!(err.starts_with("refusing to patch, 4 conflict(s)"), "{}", err);
+        assert!(err.contains("0x00000004..0x00000006 [b] overlaps 0x00000004..0x00000006 [a]"), "{}", err);
+        assert!(err.contains("0x00000005..0x00000007 [c] overlaps 0x00000004..0x00000006 [a]"), "{}", err);
+        assert!(err.contains("0x00000006..0x00000007 [d] overlaps 0x00000005..0x00000007 [c]"), "{}", err);
+    }
+
+    #[test]
+    fn check_conflicts_refuses_mutually_exclusive_recipes() {
+        let recipes = [recipe("a", "31", "41", &["b"]), recipe("b", "38", "42", &["a"]), recipe("c", "39", "43", &[])];
+        let a = candidate("a", 1, b"1", b"A");
+        let b = candidate("b", 8, b"8", b"B");
+        let c = candidate("c", 9, b"9", b"C");
+        let err = check_conflicts(&[&a, &b, &c], &recipes).unwrap_err();
+        assert!(err.starts_with("refusing to patch, 1 conflict(s)"), "{}", err);
+        assert!(err.contains("recipes [a] and [b] are mutually exclusive"), "{}", err);
+        assert_eq!(check_conflicts(&[&a, &c], &recipes).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn run_patch_writes_nothing_on_conflict() {
+        let recipes = vec![recipe("a", "34 35", "41 42", &[]), recipe("b", "35 36", "43 44", &[])];
+        let (result, output) = run("conflict", recipes, &[]);
+        assert!(result.unwrap_err().contains("[b] overlaps"));
+        assert!(!std::path::Path::new(&output).exists());
+        assert!(!std::path::Path::new(&format!("{}.patch.json", output)).exists());
+    }
+
+    #[test]
+    fn run_patch_writes_selected_patches_and_summary() {
+        let recipes = vec![recipe("a", "31 32", "41 42", &[]), recipe("b", "37", "43", &[])];
+        let (result, output) = run("summary", recipes, &["--select", "1"]);
+        result.unwrap();
+
+        let data = image();
+        let (patched, summary, diff) = take_outputs(&output);
+        let section_data = 0x48 + 0x18 + 4;
+        assert_eq!(&patched[section_data..section_data + 10], b"0123456C89");
+
+        let summary: PatchSummary = serde_json::from_str(&summary).unwrap();
+        assert_eq!(summary.output, output);
+        assert_eq!(summary.applied.len(), 1);
+        assert_eq!((summary.applied[0].offset, summary.applied[0].original.as_str(), summary.applied[0].patched.as_str()),
+            ((section_data + 7) as u64, "37", "43"));
+        assert_eq!(summary.checksums.iter().map(|c| c.kind.as_str()).collect::<Vec<_>>(), ["ffs_file"]);
+        // Patched byte and the file checksum
+        assert_eq!(summary.bytes_changed, 2);
+        assert_eq!(data.iter().zip(&patched).filter(|(a, b)| a != b).count(), 2);
+        assert!(diff);
+    }
+}
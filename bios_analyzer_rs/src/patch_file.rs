@@ -0,0 +1,188 @@
+//! Reversible patch files - JSON diff keyed by source/target SHA-256
+
+use crate::structures::*;
+use colored::Colorize;
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+
+pub const PATCH_FILE_FORMAT: &str = "bios-diff-v1";
+
+/// Runs of up to this many unchanged bytes are folded into the surrounding record
+const MERGE_GAP: usize = 8;
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Build a diff of two equally sized images
+pub fn make_patch_file(source: &[u8], target: &[u8], description: &str) -> Result<PatchFile, String> {
+    if source.len() != target.len() {
+        return Err(format!("image sizes differ ({} vs {})", source.len(), target.len()));
+    }
+
+    let mut records = Vec::new();
+    let mut i = 0;
+    while i < source.len() {
+        if source[i] == target[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i + 1;
+        let mut j = end;
+        while j < source.len() && j - end <= MERGE_GAP {
+            if source[j] != target[j] {
+                end = j + 1;
+            }
+            j += 1;
+        }
+        records.push(DiffRecord {
+            offset: start as u64,
+            original: hex::encode(&source[start..end]),
+            patched: hex::encode(&target[start..end]),
+        });
+        i = end;
+    }
+
+    Ok(PatchFile {
+        format: PATCH_FILE_FORMAT.to_string(),
+        description: description.to_string(),
+        source_size: source.len(),
+        source_sha256: sha256_hex(source),
+        target_size: target.len(),
+        target_sha256: sha256_hex(target),
+        records,
+    })
+}
+
+/// Apply a diff (or undo it with `reverse`) after checking the input hash.
+/// The result is checked against the other hash before it is returned.
+pub fn apply_patch_file(data: &[u8], patch: &PatchFile, reverse: bool) -> Result<Vec<u8>, String> {
+    if patch.format != PATCH_FILE_FORMAT {
+        return Err(format!("unsupported patch format '{}'", patch.format));
+    }
+    let (expect_size, expect_hash, result_hash) = if reverse {
+        (patch.target_size, &patch.target_sha256, &patch.source_sha256)
+    } else {
+        (patch.source_size, &patch.source_sha256, &patch.target_sha256)
+    };
+
+    if data.len() != expect_size {
+        return Err(format!("image is {} bytes, patch expects {}", data.len(), expect_size));
+    }
+    let actual = sha256_hex(data);
+    if !actual.eq_ignore_ascii_case(expect_hash) {
+        return Err(format!("image SHA-256 {} does not match patch ({})", actual, expect_hash));
+    }
+
+    let mut out = data.to_vec();
+    for record in &patch.records {
+        let original = hex::decode(&record.original).map_err(|e| e.to_string())?;
+        let patched = hex::decode(&record.patched).map_err(|e| e.to_string())?;
+        let (from, to) = if reverse { (patched, original) } else { (original, patched) };
+        // Offsets come from the file, so an overflowing range is a mismatch, not a panic
+        let range = usize::try_from(record.offset).ok()
+            .and_then(|start| Some(start..start.checked_add(from.len())?))
+            .filter(|range| from.len() == to.len() && out.get(range.clone()) == Some(&from[..]))
+            .ok_or(format!("record @ 0x{:08X} does not match the image", record.offset))?;
+        out[range].copy_from_slice(&to);
+    }
+
+    let result = sha256_hex(&out);
+    if !result.eq_ignore_ascii_case(result_hash) {
+        return Err(format!("result SHA-256 {} does not match patch ({})", result, result_hash));
+    }
+    Ok(out)
+}
+
+/// `apply-diff <input> <diff.json> <output> [--reverse]`
+pub fn run_apply_diff(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer apply-diff <input> <diff.json> <output> [--reverse]";
+    let input = args.first().ok_or(usage)?;
+    let diff_path = args.get(1).ok_or(usage)?;
+    let output = args.get(2).ok_or(usage)?;
+    let reverse = match args.get(3).map(|s| s.as_str()) {
+        None => false,
+        Some("--reverse") => true,
+        Some(other) => return Err(format!("unknown option '{}'\n{}", other, usage).into()),
+    };
+
+    println!("{}", "═".repeat(80).cyan());
+    println!("{}", " APPLY DIFF".bold().cyan());
+    println!("{}", "═".repeat(80).cyan());
+
+    let patch: PatchFile = serde_json::from_str(&std::fs::read_to_string(diff_path)?)?;
+    let file = File::open(input)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+
+    println!("  {}", patch.description);
+    println!("  {} record(s), {}", patch.records.len(), if reverse { "reverse" } else { "forward" });
+
+    let out = apply_patch_file(&mmap[..], &patch, reverse)?;
+    std::fs::write(output, &out)?;
+    println!("\n{}", format!("Image verified and saved to {}", output).green());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn images() -> (Vec<u8>, Vec<u8>) {
+        let source: Vec<u8> = (0..=255).collect();
+        let mut target = source.clone();
+        target[0x10] = 0xAA;
+        target[0x80..0x82].copy_from_slice(&[0xBB, 0xCC]);
+        (source, target)
+    }
+
+    #[test]
+    fn patch_file_round_trip() {
+        let (source, target) = images();
+        let patch = make_patch_file(&source, &target, "test").unwrap();
+        assert_eq!(patch.records.len(), 2);
+        assert_eq!((patch.records[1].offset, patch.records[1].original.as_str(), patch.records[1].patched.as_str()),
+            (0x80, "8081", "bbcc"));
+        assert_eq!(patch.source_sha256, sha256_hex(&source));
+
+        assert_eq!(apply_patch_file(&source, &patch, false).unwrap(), target);
+        assert_eq!(apply_patch_file(&target, &patch, true).unwrap(), source);
+    }
+
+    #[test]
+    fn patch_file_refuses_the_wrong_source() {
+        let (source, target) = images();
+        let patch = make_patch_file(&source, &target, "test").unwrap();
+        let mut other = source.clone();
+        other[0] = 0xFF;
+        let err = apply_patch_file(&other, &patch, false).unwrap_err();
+        assert!(err.contains("does not match patch"), "{}", err);
+        // Undoing needs the patched image, not the source
+        assert!(apply_patch_file(&source, &patch, true).is_err());
+        assert!(apply_patch_file(&source[1..], &patch, false).unwrap_err().contains("patch expects 256"));
+    }
+
+    #[test]
+    fn patch_file_merges_short_gaps() {
+        let source = vec![0u8; 64];
+        let mut target = source.clone();
+        // A gap of MERGE_GAP unchanged bytes is folded into one record, one byte more splits it
+        for i in [0, 1 + MERGE_GAP, 30, 31 + MERGE_GAP + 1] {
+            target[i] = 1;
+        }
+        let patch = make_patch_file(&source, &target, "test").unwrap();
+        let records: Vec<(u64, usize)> = patch.records.iter().map(|r| (r.offset, r.patched.len() / 2)).collect();
+        assert_eq!(records, [(0, 2 + MERGE_GAP), (30, 1), ((32 + MERGE_GAP) as u64, 1)]);
+        assert_eq!(apply_patch_file(&source, &patch, false).unwrap(), target);
+    }
+
+    #[test]
+    fn patch_file_rejects_hostile_offsets() {
+        let (source, target) = images();
+        let mut patch = make_patch_file(&source, &target, "test").unwrap();
+        patch.records[0].offset = u64::MAX;
+        let err = apply_patch_file(&source, &patch, false).unwrap_err();
+        assert!(err.contains("does not match the image"), "{}", err);
+    }
+}
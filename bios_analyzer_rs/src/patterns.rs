@@ -9,7 +9,6 @@ pub const SPD_SIGNATURE: &[u8] = &[0x23, 0x11, 0x13, 0x0E];
 
 /// Frequency table patterns
 pub const FREQ_PATTERN_51: &[u8] = &[0x51, 0x00, 0x52, 0x00, 0x53, 0x00];
-pub const FREQ_PATTERN_59: &[u8] = &[0x59, 0x00, 0x5A, 0x00, 0x5B, 0x00];
 
 /// Power limit values (in mW, little-endian u32)
 pub const POWER_15W: u32 = 15000;
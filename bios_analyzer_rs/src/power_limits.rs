@@ -0,0 +1,196 @@
+//! Platform power limits - STAPM, PPT, TDC, EDC and THM from PBS/CBS questions and setup variables
+
+use crate::analysis::*;
+use crate::ifr_parser::*;
+use crate::nvram::*;
+use crate::structures::*;
+use colored::Colorize;
+use regex::Regex;
+
+/// Limit name, prompt keywords (whole words) and the unit assumed when the prompt does not name one.
+/// Fast/Slow PPT come before plain PPT so the more specific match wins.
+const POWER_LIMITS: &[(&str, &[&str], &str)] = &[
+    ("STAPM", &["STAPM"], "mW"),
+    ("Fast PPT", &["FAST PPT", "FASTPPT", "PPT FAST"], "mW"),
+    ("Slow PPT", &["SLOW PPT", "SLOWPPT", "PPT SLOW"], "mW"),
+    ("PPT", &["PPT"], "mW"),
+    ("TDC", &["TDC"], "mA"),
+    ("EDC", &["EDC"], "mA"),
+    ("THM", &["THM", "TCTL", "TJMAX"], "C"),
+];
+
+/// Prompts that share a keyword but are not limits (STAPM time constant, TDC/EDC ramp...)
+const EXCLUDED_WORDS: &[&str] = &["TIME", "CONSTANT", "RAMP", "BOOST DURATION"];
+
+/// Plausible range per base unit; anything outside is not a limit in that unit
+const PLAUSIBLE: &[(&str, u64, u64)] = &[
+    ("mW", 1_000, 100_000),
+    ("mA", 1_000, 200_000),
+    ("C", 40, 110),
+];
+
+/// Limit kind for a question prompt
+fn limit_kind(prompt: &str) -> Option<(&'static str, &'static str)> {
+    if EXCLUDED_WORDS.iter().any(|w| contains_word(prompt, w)) {
+        return None;
+    }
+    POWER_LIMITS.iter()
+        .find(|(_, keywords, _)| keywords.iter().any(|k| contains_word(prompt, k)))
+        .map(|&(name, _, unit)| (name, unit))
+}
+
+/// Unit named in the prompt or help text, as (base unit, multiplier to it). Only a unit
+/// right after a number ("15 W") or in brackets ("(W)", "[mA]") counts, so the article
+/// in "Sets a limit" is not amps.
+fn stated_unit(text: &str, default: &'static str) -> (&'static str, u64) {
+    let unit = Regex::new(r"(?:\d\s*|[(\[])(mW|mA|W|A)\b").ok()
+        .and_then(|re| re.captures(text).map(|c| c[1].to_string()));
+    match (default, unit.as_deref()) {
+        ("mW", Some("W")) => ("mW", 1000),
+        ("mA", Some("A")) => ("mA", 1000),
+        (unit, _) => (unit, 1),
+    }
+}
+
+fn plausible(value: u64, unit: &str) -> bool {
+    PLAUSIBLE.iter().any(|&(u, min, max)| u == unit && (min..=max).contains(&value))
+}
+
+fn read_value(data: &[u8], offset: usize, size: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + size)?;
+    Some(bytes.iter().rev().fold(0u64, |v, &b| v << 8 | b as u64))
+}
+
+/// Named power limits from IFR question defaults and the matching NVRAM setup variables.
+/// Raw value matches in `report.power_structures` that are not one of these are reported as coincidental.
+pub fn analyze_power_limits(data: &[u8], report: &mut BiosReport) {
+    println!("{}", "Analyzing power limits...".dimmed());
+
+    let variables: Vec<NvramVariable> = parse_variable_stores(data).into_iter()
+        .flat_map(|store| store.variables)
+        .filter(|v| v.is_active())
+        .collect();
+    let mut rejected = 0;
+    for form_set in parse_form_sets(data) {
+        for q in &form_set.questions {
+            let Some((name, default_unit)) = limit_kind(&q.prompt) else { continue };
+            let (unit, scale) = stated_unit(&format!("{} {}", q.prompt, q.help), default_unit);
+            let varstore = form_set.varstores.iter().find(|v| v.id == q.varstore_id);
+            let variable = varstore.and_then(|vs| variables.iter()
+                .find(|v| v.name == vs.name && v.guid.eq_ignore_ascii_case(&vs.guid)));
+
+            let mut sources = Vec::new();
+            if let (Some(default), Some(offset)) = (q.default, q.default_offset) {
+                sources.push(("ifr_default", offset, default));
+            }
+            if let Some(var) = variable.filter(|v| q.var_offset as usize + q.size <= v.data_size) {
+                let offset = var.data_offset + q.var_offset as usize;
+                sources.extend(read_value(data, offset, q.size).map(|v| ("nvram", offset, v)));
+            }
+            for (source, offset, raw) in sources {
+                // 0 selects the firmware default ("Auto")
+                let value = raw * scale;
+                if raw != 0 && !plausible(value, unit) {
+                    rejected += 1;
+                    continue;
+                }
+                report.power_limits.push(PowerLimit {
+                    name: name.to_string(),
+                    value,
+                    unit: unit.to_string(),
+                    source: source.to_string(),
+                    offset: offset as u64,
+                    prompt: q.prompt.clone(),
+                    varstore: varstore.map(|v| v.name.clone()),
+                    var_offset: q.var_offset,
+                    size: q.size,
+                });
+            }
+        }
+    }
+
+    for raw in &mut report.power_structures {
+        raw.backed = report.power_limits.iter().any(|l| l.offset == raw.offset);
+    }
+    let backed = report.power_structures.iter().filter(|p| p.backed).count();
+    report.coincidental_power_matches = report.power_structures.len() - backed;
+    println!("  Found {} power limits ({} implausible values skipped), {} of {} raw power values are coincidental",
+        report.power_limits.len(), rejected, report.coincidental_power_matches, report.power_structures.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    #[test]
+    fn limit_kind_matches_whole_words() {
+        assert_eq!(limit_kind("STAPM Limit"), Some(("STAPM", "mW")));
+        assert_eq!(limit_kind("Fast PPT Limit"), Some(("Fast PPT", "mW")));
+        assert_eq!(limit_kind("PPT Limit"), Some(("PPT", "mW")));
+        assert_eq!(limit_kind("Tctl Limit"), Some(("THM", "C")));
+        assert_eq!(limit_kind("PPTC Control"), None);
+        assert_eq!(limit_kind("STAPM Time Constant"), None);
+        assert_eq!(limit_kind("EDC Ramp"), None);
+    }
+
+    #[test]
+    fn stated_unit_needs_a_number_or_brackets() {
+        assert_eq!(stated_unit("Fast PPT 15 W", "mW"), ("mW", 1000));
+        assert_eq!(stated_unit("TDC Limit [A]", "mA"), ("mA", 1000));
+        assert_eq!(stated_unit("TDC Limit (mA)", "mA"), ("mA", 1));
+        assert_eq!(stated_unit("Sets a limit for A rail", "mA"), ("mA", 1));
+        // A watt unit does not rescale current limits
+        assert_eq!(stated_unit("EDC 10 W", "mA"), ("mA", 1));
+    }
+
+    #[test]
+    fn plausible_ranges_per_unit() {
+        assert!(plausible(15_000, "mW"));
+        assert!(!plausible(999, "mW"));
+        assert!(!plausible(150_000, "mW"));
+        assert!(plausible(150_000, "mA"));
+        assert!(plausible(95, "C"));
+        assert!(!plausible(150, "C"));
+    }
+
+    #[test]
+    fn analyze_power_limits_reads_question_defaults() {
+        let data = hii_numeric_package_list(&[
+            ("STAPM Limit", "Sustained power limit", 15_000),
+            ("Fast PPT Limit", "Limit in (W)", 30),
+            ("STAPM Time Constant", "Seconds", 200),
+            ("PPTC Control", "Not a limit", 20_000),
+            ("TDC Limit", "Sets a limit", 30_000),
+            ("EDC Limit", "", 999_999),
+            ("THM Limit", "0 = Auto", 0),
+        ]);
+        let stapm_default = parse_form_sets(&data)[0].questions[0].default_offset.unwrap() as u64;
+        let mut report = BiosReport::new("test", data.len());
+        for offset in [stapm_default, 0x10] {
+            report.power_structures.push(PowerStructure {
+                offset,
+                milliwatts: 15_000,
+                watts: 15,
+                description: String::new(),
+                backed: false,
+            });
+        }
+
+        analyze_power_limits(&data, &mut report);
+        let limits: Vec<(&str, u64, &str, &str)> = report.power_limits.iter()
+            .map(|l| (l.name.as_str(), l.value, l.unit.as_str(), l.source.as_str()))
+            .collect();
+        assert_eq!(limits, [
+            ("STAPM", 15_000, "mW", "ifr_default"),
+            ("Fast PPT", 30_000, "mW", "ifr_default"),
+            ("TDC", 30_000, "mA", "ifr_default"),
+            ("THM", 0, "C", "ifr_default"),
+        ]);
+        assert_eq!((report.power_limits[0].varstore.as_deref(), report.power_limits[1].var_offset), (Some("Setup"), 4));
+
+        // Only the raw match at the STAPM default is backed by a question
+        assert_eq!(report.power_structures.iter().map(|p| p.backed).collect::<Vec<_>>(), [true, false]);
+        assert_eq!(report.coincidental_power_matches, 1);
+    }
+}
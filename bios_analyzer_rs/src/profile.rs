@@ -0,0 +1,311 @@
+//! Memory overclock profile - frequency code, SPD edits, CBS settings, recipes and H2OUVE template
+
+use crate::analysis::*;
+use crate::ifr_parser::*;
+use crate::memclk::*;
+use crate::patterns::*;
+use crate::recipes::*;
+use crate::spd::*;
+use crate::structures::*;
+use crate::timings::*;
+use crate::voltage::*;
+use colored::Colorize;
+use memmap2::Mmap;
+use serde::Serialize;
+use std::fs::File;
+
+/// Highest data rate the SPD timings are specified for
+const STOCK_RATE: u32 = 6400;
+/// SPD byte offsets rewritten to raise tCKmin: MTB value and its FTB correction
+const SPD_TCK_MIN: usize = 18;
+const SPD_TCK_MIN_FTB: usize = 125;
+
+/// Documented voltage indices (BIOS_SECRETS.md section 5); values outside the safe range
+/// in `voltage.rs` are left out of the settings
+const VOLTAGE_SETTINGS: &[(&str, u64)] = &[
+    ("VDDQ", 144),
+    ("VDD2H", 92),
+];
+
+/// CBS timing questions and the calculator timing they take
+const TIMING_SETTINGS: &[(&str, &str)] = &[
+    ("Trcdrd", "tRCD"),
+    ("Trcdwr", "tRCD"),
+    ("Trp", "tRPpb"),
+    ("Tras", "tRAS"),
+    ("Trc", "tRC"),
+];
+
+#[derive(Debug, Serialize)]
+pub struct OverclockProfile {
+    pub image: String,
+    pub part_number: String,
+    pub manufacturer: Option<String>,
+    pub target_rate: u32,
+    pub frequency: MemClkCode,
+    pub spd_edits: Vec<SpdEdit>,
+    pub timings: TimingSet,
+    pub settings: Vec<ProfileSetting>,
+    pub recipes: RecipeFile,
+    pub notes: Vec<String>,
+}
+
+/// A byte of every SPD of the part that has to change for the target rate
+#[derive(Debug, Serialize)]
+pub struct SpdEdit {
+    pub byte: usize,
+    pub name: String,
+    pub original: u8,
+    pub value: u8,
+    pub entries: Vec<u64>,
+}
+
+/// A setup variable value, located through its IFR question when the image has it
+#[derive(Debug, Serialize)]
+pub struct ProfileSetting {
+    pub name: String,
+    pub value: u64,
+    /// Documented safe range, inclusive
+    pub range: Option<(u64, u64)>,
+    pub question: Option<String>,
+    pub varstore: Option<String>,
+    pub var_offset: Option<u16>,
+    pub size: Option<usize>,
+}
+
+/// Fastest clock code that does not exceed `rate`
+fn code_for_rate(rate: u32) -> Option<(u16, u32)> {
+    (1..=0xFFu16)
+        .filter_map(|code| memclk_mhz(code).map(|mhz| (code, mhz)))
+        .filter(|&(_, mhz)| mhz * 2 <= rate)
+        .max_by_key(|&(_, mhz)| mhz)
+}
+
+/// tCKmin as MTB byte and signed FTB so that the SPD allows `tck_ps`
+fn tck_bytes(tck_ps: u32, mtb_ps: u32) -> (u8, u8) {
+    let mtb = tck_ps.div_ceil(mtb_ps);
+    let ftb = tck_ps as i64 - (mtb * mtb_ps) as i64;
+    (mtb as u8, ftb as i8 as u8)
+}
+
+fn setting(form_sets: &[IfrFormSet], name: &str, prompt: &str, value: u64) -> ProfileSetting {
+    let rail = voltage_rail(name);
+    // An exact prompt wins over a prompt that only contains the name ("Trc" vs "Trcdrd")
+    let questions = || form_sets.iter().flat_map(|fs| fs.questions.iter().map(move |q| (fs, q)));
+    let question = questions().find(|(_, q)| q.prompt.eq_ignore_ascii_case(prompt))
+        .or_else(|| questions().find(|(_, q)| name == prompt && contains_word(&q.prompt, prompt)));
+    ProfileSetting {
+        name: name.to_string(),
+        value,
+        range: rail.and_then(|r| r.safe),
+        question: question.map(|(_, q)| q.prompt.clone()),
+        varstore: question.and_then(|(fs, q)| fs.varstores.iter().find(|v| v.id == q.varstore_id).map(|v| v.name.clone())),
+        var_offset: question.map(|(_, q)| q.var_offset),
+        size: question.map(|(_, q)| q.size),
+    }
+}
+
+/// H2OUVE template: dumps each variable and lists the offsets to edit; writing back is left commented out
+fn h2ouve_template(profile: &OverclockProfile) -> String {
+    let mut script = format!(
+        "#!/bin/sh\n# Memory overclock profile: {} @ {} MT/s\n# Generated from {}\n\
+         # TEMPLATE: running it only dumps the setup variables. Change the bytes listed below in\n\
+         # each dump by hand, then uncomment its -sv line to write it back.\n\
+         # Keep the dump taken before any edit to restore stock settings.\n\nH2OUVE=${{H2OUVE:-./h2ouve-lx64}}\n",
+        profile.part_number, profile.target_rate, profile.image);
+    let mut varstores: Vec<&str> = profile.settings.iter().filter_map(|s| s.varstore.as_deref()).collect();
+    varstores.sort_unstable();
+    varstores.dedup();
+    for store in varstores {
+        script.push_str(&format!("\n\"$H2OUVE\" -gv {store}.txt -n {store}\n"));
+        for s in profile.settings.iter().filter(|s| s.varstore.as_deref() == Some(store)) {
+            script.push_str(&format!("#   {} ({}): offset 0x{:04X}, {} byte(s) = 0x{:X}\n",
+                s.name, s.question.as_deref().unwrap_or("-"), s.var_offset.unwrap_or(0), s.size.unwrap_or(1), s.value));
+        }
+        script.push_str(&format!("# \"$H2OUVE\" -sv {store}.txt -n {store}\n"));
+    }
+    for s in profile.settings.iter().filter(|s| s.varstore.is_none()) {
+        script.push_str(&format!("\n# {} = 0x{:X} ({}): no IFR question in this image, set it in the CBS/PBS menu\n",
+            s.name, s.value, s.value));
+    }
+    script
+}
+
+/// `profile <image> --rate 7000 [--part K3LKBKB0BM]`
+pub fn run_profile(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer profile <image> --rate <MT/s> [--part <part-number prefix>]";
+    let image = args.first().ok_or(usage)?;
+    let mut rate = None;
+    let mut part = String::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match (arg.as_str(), rest.next()) {
+            ("--rate", Some(r)) => rate = Some(r.parse::<u32>().map_err(|e| format!("bad rate '{}': {}", r, e))?),
+            ("--part", Some(p)) => part = p.clone(),
+            _ => return Err(usage.into()),
+        }
+    }
+    let rate = rate.filter(|&r| r > 0).ok_or(usage)?;
+
+    println!("{}", "═".repeat(80).cyan());
+    println!("{}", " MEMORY OVERCLOCK PROFILE".bold().cyan());
+    println!("{}", "═".repeat(80).cyan());
+
+    let file = File::open(image)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let data = &mmap[..];
+
+    let spds: Vec<(usize, SpdInfo)> = find_pattern(data, SPD_SIGNATURE).into_iter()
+        .filter_map(|o| decode_spd(data, o).map(|info| (o, info)))
+        .filter(|(_, info)| info.part_number.starts_with(&part))
+        .collect();
+    let mut parts: Vec<&str> = spds.iter().map(|(_, i)| i.part_number.as_str()).collect();
+    parts.sort_unstable();
+    parts.dedup();
+    let info = match parts.as_slice() {
+        [] => return Err("no matching LPDDR5 SPD found".into()),
+        [_] => &spds[0].1,
+        _ => return Err(format!("several parts match, pick one with --part: {}", parts.join(", ")).into()),
+    };
+    let part_number = info.part_number.clone();
+    let mut notes = Vec::new();
+
+    // Frequency code
+    let (code, mhz) = code_for_rate(rate).ok_or(format!("no clock code at or below {} MT/s", rate))?;
+    let exposed = exposed_memclk_codes(data);
+    let frequency = MemClkCode {
+        code,
+        mhz: Some(mhz),
+        data_rate: Some(mhz * 2),
+        exposed: exposed.contains(&code),
+        note: memclk_note(code).to_string(),
+    };
+    if mhz * 2 != rate {
+        notes.push(format!("{} MT/s has no clock code, using 0x{:02X} ({} MT/s)", rate, code, mhz * 2));
+    }
+    let rate = mhz * 2;
+
+    // SPD edits and recipes
+    let mut recipes = Vec::new();
+    let mut spd_edits = Vec::new();
+    let tck_ps = tck_ps_for_rate(rate);
+    if info.tck_min_ps > tck_ps {
+        let (mtb, ftb) = tck_bytes(tck_ps, info.mtb_ps);
+        for (byte, name, value) in [(SPD_TCK_MIN, "tCKmin", mtb), (SPD_TCK_MIN_FTB, "tCKmin FTB", ftb)] {
+            let entries: Vec<(usize, u8)> = spds.iter()
+                .filter(|(o, i)| i.part_number == part_number && data[o + byte] != value)
+                .map(|(o, _)| (*o, data[o + byte]))
+                .collect();
+            let Some(&(_, original)) = entries.first() else { continue };
+            if entries.iter().any(|&(_, b)| b != original) {
+                notes.push(format!("SPD byte {} differs between entries, recipe covers 0x{:02X} only", byte, original));
+            }
+            recipes.push(PatchRecipe {
+                name: format!("spd-{}-{}", name.to_lowercase().replace(' ', "-"), rate),
+                description: format!("SPD {} for {} MT/s", name, rate),
+                effect: format!("{} allows {} MT/s", part_number, rate),
+                risk: "low".to_string(),
+                locator: Locator::Spd { part: part_number.clone(), byte },
+                expected: format!("{:02X}", original),
+                replacement: format!("{:02X}", value),
+                versions: Vec::new(),
+                conflicts_with: Vec::new(),
+            });
+            spd_edits.push(SpdEdit {
+                byte,
+                name: name.to_string(),
+                original,
+                value,
+                entries: entries.iter().map(|&(o, _)| o as u64).collect(),
+            });
+        }
+    }
+
+    let form_sets = parse_form_sets(data);
+    if !frequency.exposed {
+        let menu_top = find_questions(&form_sets, MEMORY_CLOCK_QUESTION).into_iter()
+            .flat_map(|q| q.options.iter())
+            .filter(|o| o.conditions.is_empty() && memclk_mhz(o.value as u16).is_some())
+            .max_by_key(|o| o.value);
+        match menu_top {
+            Some(option) => recipes.push(PatchRecipe {
+                name: format!("memory-clock-remap-{}", rate),
+                description: format!("Frequency remap ({} -> 0x{:02X})", option.text, code),
+                effect: format!("{} selection -> {} MT/s", option.text, rate),
+                risk: "low".to_string(),
+                locator: Locator::IfrOption {
+                    question: MEMORY_CLOCK_QUESTION.to_string(),
+                    option: OptionRef::Text(option.text.clone()),
+                },
+                expected: format!("{:02X}", option.value),
+                replacement: format!("{:02X}", code),
+                versions: Vec::new(),
+                conflicts_with: Vec::new(),
+            }),
+            None => notes.push(format!("no '{}' question found, remap or add-options by hand", MEMORY_CLOCK_QUESTION)),
+        }
+    }
+
+    // Setup values: clock code, JEDEC timings, documented voltages
+    let timings = calculate_timings(info, rate);
+    let mut settings = vec![setting(&form_sets, "Memory Clock Speed", MEMORY_CLOCK_QUESTION, code as u64)];
+    for &(prompt, timing) in TIMING_SETTINGS {
+        if let Some(t) = timings.timings.iter().find(|t| t.name == timing) {
+            settings.push(setting(&form_sets, prompt, prompt, t.clocks as u64));
+        }
+    }
+    if rate > STOCK_RATE {
+        for &(prompt, value) in VOLTAGE_SETTINGS {
+            match voltage_rail(prompt).and_then(|r| voltage_warning(r, value)) {
+                Some(warning) => notes.push(format!("{}, not set", warning)),
+                None => settings.push(setting(&form_sets, prompt, prompt, value)),
+            }
+        }
+    }
+    if timings.over_spec {
+        notes.push("target is above the SPD tCKmin: timings are JEDEC-rounded starting points, test stability".to_string());
+    }
+
+    let profile = OverclockProfile {
+        image: image.clone(),
+        part_number,
+        manufacturer: info.manufacturer.clone(),
+        target_rate: rate,
+        frequency,
+        spd_edits,
+        timings,
+        settings,
+        recipes: RecipeFile { recipes },
+        notes,
+    };
+
+    println!("\n  {} {} @ {} MT/s", profile.manufacturer.as_deref().unwrap_or("unknown"), profile.part_number, rate);
+    println!("  Clock code 0x{:02X} ({} MHz) [{}]", code, mhz,
+        if profile.frequency.exposed { "menu".green() } else { "hidden".yellow() });
+    for edit in &profile.spd_edits {
+        println!("  SPD byte {} ({}): {:02X} -> {:02X} in {} entr{}", edit.byte, edit.name, edit.original, edit.value,
+            edit.entries.len(), if edit.entries.len() == 1 { "y" } else { "ies" });
+    }
+    print_timing_set(&profile.timings);
+    println!("\n  {}", "Settings:".bold());
+    for s in &profile.settings {
+        let location = match (&s.varstore, s.var_offset) {
+            (Some(store), Some(offset)) => format!("{}+0x{:04X}", store, offset),
+            _ => "menu only".to_string(),
+        };
+        println!("    {:<20} = {:<5} {}", s.name, s.value, location);
+    }
+    for recipe in &profile.recipes.recipes {
+        println!("  Recipe {}: {} -> {}", recipe.name, recipe.expected, recipe.replacement);
+    }
+    for note in &profile.notes {
+        println!("  {} {}", "note:".yellow(), note);
+    }
+
+    let base = format!("{}.profile-{}", image, rate);
+    std::fs::write(format!("{}.json", base), serde_json::to_string_pretty(&profile)?)?;
+    std::fs::write(format!("{}.recipes.json", base), serde_json::to_string_pretty(&profile.recipes)?)?;
+    std::fs::write(format!("{}.h2ouve-template.sh", base), h2ouve_template(&profile))?;
+    println!("\n{}", format!("Profile saved to {0}.json, recipes to {0}.recipes.json, H2OUVE template to {0}.h2ouve-template.sh", base).green());
+    Ok(())
+}
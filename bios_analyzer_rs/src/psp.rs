@@ -0,0 +1,187 @@
+//! AMD PSP structures - Embedded Firmware Structure and PSP/BIOS directories
+
+use crate::analysis::*;
+use serde::Serialize;
+
+/// Embedded Firmware Structure signature (0x55AA55AA)
+const EFS_SIGNATURE: &[u8] = &[0xAA, 0x55, 0xAA, 0x55];
+/// Flash offset the EFS normally sits at, used to find the flash base
+const EFS_FLASH_OFFSET: usize = 0x20000;
+/// EFS pointer slots: PSP directory, combo PSP directory, BIOS directories
+const EFS_POINTERS: &[(usize, &str)] = &[
+    (0x10, "psp_legacy"),
+    (0x14, "psp"),
+    (0x18, "bios_f17_00"),
+    (0x1C, "bios_f17_10"),
+    (0x20, "bios_f17_30"),
+    (0x28, "bios_f17_60"),
+];
+
+const DIRECTORY_SIGNATURES: &[&[u8]] = &[b"$PSP", b"$PL2", b"$BHD", b"$BL2", b"2PSP", b"2BHD"];
+const MAX_DIRECTORY_ENTRIES: usize = 256;
+
+/// BIOS directory entry type the PSP verifies before releasing x86 (BIOS reset image)
+pub const BIOS_ENTRY_RESET_IMAGE: u8 = 0x62;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Efs {
+    pub offset: usize,
+    /// Image offset of flash address 0
+    pub flash_base: usize,
+    /// (slot, raw pointer, resolved image offset)
+    pub pointers: Vec<(String, u32, Option<usize>)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PspDirectory {
+    pub offset: usize,
+    pub signature: String,
+    pub checksum: u32,
+    pub checksum_valid: bool,
+    /// Header + entries, the range covered by the checksum (after the first 8 bytes)
+    pub size: usize,
+    pub entries: Vec<PspDirEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PspDirEntry {
+    pub entry_type: u8,
+    pub subprogram: u8,
+    pub size: u32,
+    pub location: u64,
+    /// Image offset of the entry body, if it maps into the image
+    pub offset: Option<usize>,
+}
+
+/// AMD's Fletcher-32 over little-endian 16-bit words. Sums fold with end-around
+/// carry like PSPTool, so a zero sum stays 0xFFFF instead of wrapping to 0.
+pub fn fletcher32(bytes: &[u8]) -> u32 {
+    let fold = |x: u32| (x & 0xFFFF) + (x >> 16);
+    let (mut c0, mut c1) = (0xFFFFu32, 0xFFFFu32);
+    for w in bytes.chunks_exact(2) {
+        c0 = fold(c0 + u16::from_le_bytes([w[0], w[1]]) as u32);
+        c1 = fold(c1 + c0);
+    }
+    (c1 << 16) | c0
+}
+
+/// Map a directory pointer (memory-mapped 0xFFxxxxxx or flash offset) into the image
+pub fn resolve_location(location: u64, flash_base: usize, image_len: usize) -> Option<usize> {
+    let flash = (location & 0x00FF_FFFF) as usize;
+    let offset = flash_base + flash;
+    (offset < image_len).then_some(offset)
+}
+
+/// Find the EFS on a 4 KB boundary
+pub fn find_efs(data: &[u8]) -> Option<Efs> {
+    let offset = find_pattern(data, EFS_SIGNATURE)
+        .into_iter()
+        .find(|&o| o % 0x1000 == 0 && o + 0x40 <= data.len())?;
+    let flash_base = offset.saturating_sub(EFS_FLASH_OFFSET);
+    let pointers = EFS_POINTERS.iter()
+        .filter_map(|&(at, name)| {
+            let raw = read_u32(data, offset + at)?;
+            if raw == 0 || raw == u32::MAX {
+                return None;
+            }
+            Some((name.to_string(), raw, resolve_location(raw as u64, flash_base, data.len())))
+        })
+        .collect();
+    Some(Efs { offset, flash_base, pointers })
+}
+
+/// Find and parse all PSP / BIOS / combo directories
+pub fn parse_psp_directories(data: &[u8]) -> Vec<PspDirectory> {
+    let flash_base = find_efs(data).map(|e| e.flash_base).unwrap_or(0);
+    let mut dirs = Vec::new();
+    for sig in DIRECTORY_SIGNATURES {
+        for offset in find_pattern(data, sig) {
+            if let Some(dir) = parse_directory(data, offset, flash_base) {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs.sort_by_key(|d| d.offset);
+    dirs
+}
+
+fn parse_directory(data: &[u8], offset: usize, flash_base: usize) -> Option<PspDirectory> {
+    let signature = String::from_utf8_lossy(&data[offset..offset + 4]).into_owned();
+    let checksum = read_u32(data, offset + 4)?;
+    let count = read_u32(data, offset + 8)? as usize;
+    if count == 0 || count > MAX_DIRECTORY_ENTRIES {
+        return None;
+    }
+
+    let (header, entry_size) = match signature.as_str() {
+        "2PSP" | "2BHD" => (32, 16),
+        "$BHD" | "$BL2" => (16, 24),
+        _ => (16, 16),
+    };
+    let size = header + count * entry_size;
+    if offset + size > data.len() {
+        return None;
+    }
+
+    let entries = (0..count)
+        .map(|i| {
+            let e = offset + header + i * entry_size;
+            let (entry_type, subprogram, size, location) = match entry_size {
+                24 => (data[e], data[e + 3], read_u32(data, e + 4).unwrap_or(0), read_u64(data, e + 8).unwrap_or(0)),
+                _ if header == 32 => (data[e + 4], 0, 0, read_u64(data, e + 8).unwrap_or(0)),
+                _ => (data[e], data[e + 1], read_u32(data, e + 4).unwrap_or(0), read_u64(data, e + 8).unwrap_or(0)),
+            };
+            PspDirEntry {
+                entry_type,
+                subprogram,
+                size,
+                location,
+                offset: resolve_location(location, flash_base, data.len()),
+            }
+        })
+        .collect();
+
+    Some(PspDirectory {
+        offset,
+        signature,
+        checksum,
+        checksum_valid: fletcher32(&data[offset + 8..offset + size]) == checksum,
+        size,
+        entries,
+    })
+}
+
+/// Image ranges the PSP verifies before handing over to the BIOS:
+/// PSP firmware entries and the BIOS reset image
+pub fn signed_ranges(dirs: &[PspDirectory]) -> Vec<(usize, usize, String)> {
+    let mut ranges = Vec::new();
+    for dir in dirs {
+        let bios = dir.signature.contains("BHD") || dir.signature == "$BL2";
+        for entry in &dir.entries {
+            let Some(offset) = entry.offset else { continue };
+            if entry.size == 0 || entry.size == u32::MAX {
+                continue;
+            }
+            if !bios || entry.entry_type == BIOS_ENTRY_RESET_IMAGE {
+                ranges.push((offset, entry.size as usize,
+                    format!("{}@0x{:08X} entry 0x{:02X}", dir.signature, dir.offset, entry.entry_type)));
+            }
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fletcher32_known_answers() {
+        // Values from PSPTool's fletcher32
+        assert_eq!(fletcher32(b"abcdef"), 0x5650_2D2A);
+        assert_eq!(fletcher32(&(0..=255u8).cycle().take(1024).collect::<Vec<_>>()), 0x1615_FF00);
+        // Sums of 0xFFFF stay 0xFFFF, a modulo would give 0
+        assert_eq!(fletcher32(&[0, 0]), 0xFFFF_FFFF);
+        assert_eq!(fletcher32(&[]), 0xFFFF_FFFF);
+    }
+}
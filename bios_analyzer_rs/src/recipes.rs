@@ -0,0 +1,375 @@
+//! Declarative patch recipes - loaded from JSON and resolved against an image
+
+use crate::analysis::*;
+use crate::apcb::*;
+use crate::ffs::*;
+use crate::identity::*;
+use crate::ifr_parser::*;
+use crate::patterns::*;
+use crate::spd::*;
+use crate::structures::*;
+use crate::voltage::*;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::cell::OnceCell;
+
+/// Recipes shipped with the analyzer (recipes/default.json)
+pub const DEFAULT_RECIPES: &str = include_str!("../recipes/default.json");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipeFile {
+    pub recipes: Vec<PatchRecipe>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchRecipe {
+    pub name: String,
+    pub description: String,
+    pub effect: String,
+    pub risk: String,
+    pub locator: Locator,
+    /// Hex bytes that must be present at the target ("0A", "59 00")
+    pub expected: String,
+    /// Hex bytes written in place of `expected`
+    pub replacement: String,
+    /// BIOS versions the recipe applies to; empty means any
+    #[serde(default)]
+    pub versions: Vec<String>,
+    /// Recipes that must not be applied together with this one
+    #[serde(default)]
+    pub conflicts_with: Vec<String>,
+}
+
+/// Where a recipe's target bytes live
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Locator {
+    /// Every match of `pattern`, target at match + `offset`
+    Pattern {
+        pattern: String,
+        #[serde(default)]
+        offset: i64,
+    },
+    /// Inside an FFS file: file data, or the `index`-th section of type `section`
+    Ffs {
+        guid: String,
+        #[serde(default)]
+        section: Option<String>,
+        #[serde(default)]
+        index: usize,
+        #[serde(default)]
+        offset: usize,
+    },
+    /// Value of an APCB token, in every APCB instance
+    ApcbToken {
+        token: u32,
+    },
+    /// Value of a ONE_OF option of the IFR question with prompt `question`
+    IfrOption {
+        question: String,
+        option: OptionRef,
+    },
+    /// Byte `byte` of every LPDDR5 SPD whose part number starts with `part` (empty: all)
+    Spd {
+        #[serde(default)]
+        part: String,
+        byte: usize,
+    },
+}
+
+/// IFR option by position or by its (case-insensitive) text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OptionRef {
+    Index(usize),
+    Text(String),
+}
+
+/// Parsed image structures, built on first use by a structural locator
+struct Layout<'a> {
+    data: &'a [u8],
+    volumes: OnceCell<Vec<FirmwareVolume>>,
+    apcbs: OnceCell<Vec<Apcb>>,
+    form_sets: OnceCell<Vec<IfrFormSet>>,
+}
+
+impl<'a> Layout<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Layout { data, volumes: OnceCell::new(), apcbs: OnceCell::new(), form_sets: OnceCell::new() }
+    }
+
+    fn volumes(&self) -> &[FirmwareVolume] {
+        self.volumes.get_or_init(|| parse_firmware_volumes(self.data))
+    }
+
+    fn apcbs(&self) -> &[Apcb] {
+        self.apcbs.get_or_init(|| parse_apcbs(self.data))
+    }
+
+    fn form_sets(&self) -> &[IfrFormSet] {
+        self.form_sets.get_or_init(|| parse_form_sets(self.data))
+    }
+}
+
+/// Parse "59 00 5A" / "59005a" style hex strings
+pub fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    let compact: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    hex::decode(&compact).map_err(|e| format!("bad hex '{}': {}", s, e))
+}
+
+/// Load recipes from a JSON file, or the bundled defaults when `path` is None
+pub fn load_recipes(path: Option<&str>) -> Result<Vec<PatchRecipe>, String> {
+    let text = match path {
+        Some(p) => std::fs::read_to_string(p).map_err(|e| format!("{}: {}", p, e))?,
+        None => DEFAULT_RECIPES.to_string(),
+    };
+    parse_recipes(&text, path.unwrap_or("default recipes"))
+}
+
+/// Parse and check a recipe file; `source` names it in errors
+fn parse_recipes(text: &str, source: &str) -> Result<Vec<PatchRecipe>, String> {
+    let file: RecipeFile = serde_json::from_str(text).map_err(|e| format!("{}: {}", source, e))?;
+
+    for recipe in &file.recipes {
+        let expected = parse_hex_bytes(&recipe.expected)?;
+        let replacement = parse_hex_bytes(&recipe.replacement)?;
+        if expected.is_empty() || expected.len() != replacement.len() {
+            return Err(format!("recipe '{}': expected and replacement must be the same non-zero length",
+                recipe.name));
+        }
+        if let Locator::Spd { byte, .. } = recipe.locator {
+            if byte.checked_add(expected.len()).is_none_or(|end| end > SPD_MIN_SIZE) {
+                return Err(format!("recipe '{}': SPD byte {} is outside the {}-byte SPD",
+                    recipe.name, byte, SPD_MIN_SIZE));
+            }
+        }
+    }
+    Ok(file.recipes)
+}
+
+/// Resolve a locator to candidate target offsets for `len` target bytes
+fn locate(layout: &Layout, locator: &Locator, len: usize) -> Result<Vec<usize>, String> {
+    match locator {
+        Locator::Pattern { pattern, offset } => {
+            let bytes = parse_hex_bytes(pattern)?;
+            Ok(find_pattern(layout.data, &bytes)
+                .into_iter()
+                .filter_map(|hit| usize::try_from(hit as i64 + offset).ok())
+                .collect())
+        }
+        Locator::Ffs { guid, section, index, offset } => {
+            let section_type = match section {
+                Some(name) => Some(section_type_by_name(name)
+                    .ok_or(format!("unknown section type '{}'", name))?),
+                None => None,
+            };
+            let mut targets = Vec::new();
+            for file in all_files(layout.volumes()) {
+                if !file.guid.eq_ignore_ascii_case(guid) {
+                    continue;
+                }
+                // Target bytes must stay inside the chosen section or file
+                let region = match section_type {
+                    Some(t) => flatten_sections(&file.sections)
+                        .into_iter()
+                        .filter(|s| s.section_type == t)
+                        .nth(*index)
+                        .map(|s| (s.data_offset(), s.offset + s.size)),
+                    None => Some((file.data_offset(), file.offset + file.size)),
+                };
+                let Some((base, end)) = region else { continue };
+                let target = base.checked_add(*offset)
+                    .filter(|t| t.checked_add(len).is_some_and(|e| e <= end))
+                    .ok_or(format!("offset 0x{:X} + {} byte(s) is outside the {} of FFS file {} (0x{:X} data bytes)",
+                        offset, len, if section_type.is_some() { "section" } else { "file" }, file.guid, end.saturating_sub(base)))?;
+                targets.push(target);
+            }
+            Ok(targets)
+        }
+        Locator::ApcbToken { token } => Ok(layout.apcbs()
+            .iter()
+            .flat_map(|apcb| apcb.tokens.iter())
+            .filter(|t| t.id == *token)
+            .map(|t| t.offset)
+            .collect()),
+        Locator::IfrOption { question, option } => {
+            let mut targets = Vec::new();
+            for q in find_questions(layout.form_sets(), question) {
+                let found = match option {
+                    OptionRef::Index(i) => q.options.get(*i),
+                    OptionRef::Text(text) => q.options.iter().find(|o| o.text.eq_ignore_ascii_case(text)),
+                };
+                targets.extend(found.map(|o| o.value_offset));
+            }
+            Ok(targets)
+        }
+        Locator::Spd { part, byte } => Ok(find_pattern(layout.data, SPD_SIGNATURE)
+            .into_iter()
+            .filter(|&o| decode_spd(layout.data, o).is_some_and(|spd| spd.part_number.starts_with(part.as_str())))
+            .map(|o| o + byte)
+            .collect()),
+    }
+}
+
+/// Resolve recipes against the image and add the resulting patch candidates.
+/// Sites where the expected bytes are not present are skipped.
+pub fn resolve_recipes(data: &[u8], recipes: &[PatchRecipe], report: &mut BiosReport) -> Result<(), String> {
+    println!("{}", "Resolving patch recipes...".dimmed());
+
+    let version = find_bios_version(data);
+    let layout = Layout::new(data);
+    for recipe in recipes {
+        if !recipe.versions.is_empty()
+            && !version.as_ref().is_some_and(|v| recipe.versions.contains(v)) {
+            println!("  {} skipped: applies to {:?}, image is {}", recipe.name,
+                recipe.versions, version.as_deref().unwrap_or("unknown"));
+            continue;
+        }
+
+        let expected = parse_hex_bytes(&recipe.expected)?;
+        let replacement = parse_hex_bytes(&recipe.replacement)?;
+        let mut hits = 0;
+        for target in locate(&layout, &recipe.locator, expected.len())? {
+            if data.get(target..target + expected.len()) != Some(&expected[..]) {
+                continue;
+            }
+            report.patches.push(PatchCandidate {
+                recipe: recipe.name.clone(),
+                offset: target as u64,
+                original: expected.clone(),
+                patched: replacement.clone(),
+                description: recipe.description.clone(),
+                effect: recipe.effect.clone(),
+                risk: recipe.risk.clone(),
+            });
+            hits += 1;
+        }
+        println!("  {}: {} site(s)", recipe.name, hits);
+        if let Locator::IfrOption { question, .. } = &recipe.locator {
+            let index = replacement.iter().rev().fold(0u64, |v, &b| v << 8 | b as u64);
+            if let Some(warning) = voltage_rail(question).and_then(|r| voltage_warning(r, index)) {
+                println!("    {} {}", "warning".yellow(), warning);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    fn recipe_json(locator: &str, expected: &str) -> String {
+        format!(r#"{{"recipes": [{{"name": "r", "description": "", "effect": "", "risk": "low",
+            "locator": {}, "expected": "{}", "replacement": "{}"}}]}}"#, locator, expected, expected)
+    }
+
+    fn ffs_locator(section: Option<&str>, offset: usize) -> Locator {
+        Locator::Ffs {
+            guid: "22222222-2222-2222-2222-222222222222".to_string(),
+            section: section.map(str::to_string),
+            index: 0,
+            offset,
+        }
+    }
+
+    #[test]
+    fn ffs_locator_targets_section_or_file_data() {
+        let data = volume(&[ffs_file(0x22, &section(SECTION_RAW, b"0123456789"))], 0x1000);
+        let layout = Layout::new(&data);
+        let file_data = 0x48 + 0x18;
+        assert_eq!(locate(&layout, &ffs_locator(Some("raw"), 2), 1), Ok(vec![file_data + 4 + 2]));
+        assert_eq!(locate(&layout, &ffs_locator(None, 2), 1), Ok(vec![file_data + 2]));
+        assert_eq!(locate(&layout, &ffs_locator(Some("PE32"), 0), 1), Ok(vec![]));
+        assert!(locate(&layout, &ffs_locator(Some("LZMA"), 0), 1).unwrap_err().contains("unknown section type"));
+    }
+
+    #[test]
+    fn ffs_locator_rejects_targets_past_the_section_or_file() {
+        let data = volume(&[ffs_file(0x22, &section(SECTION_RAW, b"0123456789"))], 0x1000);
+        let layout = Layout::new(&data);
+        // Last byte of the section data fits, one byte more runs into the padding and the next file
+        assert!(locate(&layout, &ffs_locator(Some("RAW"), 9), 1).is_ok());
+        let err = locate(&layout, &ffs_locator(Some("RAW"), 9), 2).unwrap_err();
+        assert!(err.contains("outside the section"), "{}", err);
+        // File data is the 4-byte section header, 10 bytes and 2 bytes of alignment
+        assert!(locate(&layout, &ffs_locator(None, 15), 1).is_ok());
+        assert!(locate(&layout, &ffs_locator(None, 16), 1).unwrap_err().contains("outside the file"));
+        assert!(locate(&layout, &ffs_locator(None, usize::MAX), 1).is_err());
+    }
+
+    #[test]
+    fn apcb_token_locator_targets_token_values() {
+        let mut apcb = b"APCB".to_vec();
+        apcb.extend(0x20u16.to_le_bytes());
+        apcb.extend(0x30u16.to_le_bytes());
+        apcb.extend(0x48u32.to_le_bytes());
+        apcb.resize(0x20, 0);
+        apcb.extend(b"TOKN");
+        apcb.extend(APCB_GROUP_TOKEN.to_le_bytes());
+        apcb.extend(16u16.to_le_bytes());
+        apcb.extend([0; 4]);
+        apcb.extend(0x28u32.to_le_bytes());
+        // Byte token entry: group, type 0, size 0x18, then token 0xAE46CBF1 = 0x12
+        apcb.extend(APCB_GROUP_TOKEN.to_le_bytes());
+        apcb.extend([0; 2]);
+        apcb.extend(0x18u16.to_le_bytes());
+        apcb.extend([0; 10]);
+        apcb.extend(0xAE46_CBF1u32.to_le_bytes());
+        apcb.extend(0x12u32.to_le_bytes());
+        let data = [vec![0xFF; 0x10], apcb.clone(), apcb].concat();
+
+        let layout = Layout::new(&data);
+        let token = |token| locate(&layout, &Locator::ApcbToken { token }, 1);
+        assert_eq!(token(0xAE46_CBF1), Ok(vec![0x10 + 0x44, 0x58 + 0x44]));
+        assert_eq!(token(0x1234), Ok(vec![]));
+    }
+
+    #[test]
+    fn ifr_option_locator_targets_option_values() {
+        let data = hii_package_list();
+        let layout = Layout::new(&data);
+        let option = |option| locate(&layout, &Locator::IfrOption {
+            question: "memory clock speed".to_string(),
+            option,
+        }, 1).unwrap();
+
+        let by_text = option(OptionRef::Text("3733mhz".to_string()));
+        assert_eq!(by_text.iter().map(|&o| data[o]).collect::<Vec<_>>(), [0x5F]);
+        let by_index = option(OptionRef::Index(1));
+        assert_eq!(by_index.iter().map(|&o| data[o]).collect::<Vec<_>>(), [0x59]);
+        assert!(option(OptionRef::Index(3)).is_empty());
+        assert!(option(OptionRef::Text("4000MHZ".to_string())).is_empty());
+    }
+
+    #[test]
+    fn spd_locator_filters_by_part_number() {
+        let mut spd = mgcp_spd();
+        spd[1] = 0x11;
+        let data = [vec![0xFF; 0x10], spd.clone(), vec![0xFF; 0x10], spd].concat();
+        let second = 0x10 + 512 + 0x10;
+
+        let layout = Layout::new(&data);
+        let spd = |part: &str| locate(&layout, &Locator::Spd { part: part.to_string(), byte: 18 }, 1).unwrap();
+        assert_eq!(spd("K3LKBKB0BM"), [0x10 + 18, second + 18]);
+        assert_eq!(spd(""), [0x10 + 18, second + 18]);
+        assert!(spd("MT62F").is_empty());
+    }
+
+    #[test]
+    fn spd_recipes_must_stay_inside_the_spd() {
+        let locator = |byte| format!(r#"{{"kind": "spd", "part": "K3LK", "byte": {}}}"#, byte);
+        assert!(parse_recipes(&recipe_json(&locator(383), "00"), "test").is_ok());
+        let err = parse_recipes(&recipe_json(&locator(383), "00 00"), "test").unwrap_err();
+        assert!(err.contains("outside the 384-byte SPD"), "{}", err);
+        assert!(parse_recipes(&recipe_json(&locator(384), "00"), "test").is_err());
+    }
+
+    #[test]
+    fn default_recipes_use_the_ifr_locator_for_the_memory_clock() {
+        let recipes = load_recipes(None).unwrap();
+        let remap = recipes.iter().find(|r| r.name == "memory-clock-remap").unwrap();
+        assert!(matches!(remap.locator, Locator::IfrOption { .. }));
+    }
+}
@@ -0,0 +1,270 @@
+//! Region classifier - padding, compressed, code, data, strings and PSP blobs
+
+use crate::analysis::*;
+use crate::entropy::*;
+use crate::ffs::*;
+use crate::psp::*;
+use crate::structures::*;
+use serde::{Deserialize, Serialize};
+
+/// Classification unit in bytes
+pub const REGION_GRANULE: usize = 0x100;
+
+/// PE section characteristics: contains code / executable
+const IMAGE_SCN_CNT_CODE: u32 = 0x20;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const PE_SECTION_HEADER: usize = 40;
+const TE_HEADER_SIZE: usize = 40;
+
+/// Share of bytes that must be text, non-zero, and of those letters, for a granule to count as strings
+const TEXT_RATIO: f64 = 0.95;
+const FILL_RATIO: f64 = 0.25;
+const LETTER_RATIO: f64 = 0.6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionKind {
+    Padding,
+    Compressed,
+    Code,
+    Data,
+    Strings,
+    Psp,
+}
+
+impl RegionKind {
+    pub const ALL: [RegionKind; 6] = [
+        RegionKind::Padding, RegionKind::Compressed, RegionKind::Code,
+        RegionKind::Data, RegionKind::Strings, RegionKind::Psp,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RegionKind::Padding => "padding",
+            RegionKind::Compressed => "compressed",
+            RegionKind::Code => "code",
+            RegionKind::Data => "data",
+            RegionKind::Strings => "strings",
+            RegionKind::Psp => "psp",
+        }
+    }
+}
+
+/// Kind of every granule of the image
+pub struct RegionMap {
+    granules: Vec<RegionKind>,
+    len: usize,
+    /// Entropy of every ENTROPY_BLOCK, bits per byte
+    pub entropy: Vec<f64>,
+}
+
+/// Code ranges of a PE32 or TE image at `start`; None if the headers do not parse
+fn image_code_ranges(data: &[u8], start: usize, end: usize) -> Option<Vec<(usize, usize)>> {
+    let image = data.get(start..end)?;
+    let (table, count, base) = match image.get(..2)? {
+        b"MZ" => {
+            let pe = read_u32(image, 0x3C)? as usize;
+            if image.get(pe..pe + 4)? != b"PE\0\0" {
+                return None;
+            }
+            let count = read_u16(image, pe + 6)? as usize;
+            let optional = read_u16(image, pe + 20)? as usize;
+            (pe + 24 + optional, count, 0isize)
+        }
+        b"VZ" => {
+            let stripped = read_u16(image, 6)? as isize;
+            (TE_HEADER_SIZE, *image.get(4)? as usize, TE_HEADER_SIZE as isize - stripped)
+        }
+        _ => return None,
+    };
+    let mut ranges = Vec::new();
+    for i in 0..count {
+        let header = table + i * PE_SECTION_HEADER;
+        let raw_size = read_u32(image, header + 16)? as usize;
+        let raw_pointer = read_u32(image, header + 20)? as isize + base;
+        let characteristics = read_u32(image, header + 36)?;
+        if characteristics & (IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE) != 0 && raw_pointer >= 0 {
+            let from = start + raw_pointer as usize;
+            ranges.push((from.min(end), (from + raw_size).min(end)));
+        }
+    }
+    Some(ranges)
+}
+
+/// Content-only class of a granule not covered by a known structure
+fn classify_content(bytes: &[u8]) -> RegionKind {
+    if bytes.iter().all(|&b| b == 0xFF) || bytes.iter().all(|&b| b == 0) {
+        return RegionKind::Padding;
+    }
+    let text = bytes.iter().filter(|&&b| b == 0 || b.is_ascii_graphic() || b.is_ascii_whitespace()).count();
+    let nonzero = bytes.iter().filter(|&&b| b != 0).count();
+    let letters = bytes.iter().filter(|&&b| b.is_ascii_alphabetic() || b == b' ').count();
+    let len = bytes.len() as f64;
+    if text as f64 >= len * TEXT_RATIO && nonzero as f64 >= len * FILL_RATIO && letters as f64 >= nonzero as f64 * LETTER_RATIO {
+        RegionKind::Strings
+    } else {
+        RegionKind::Data
+    }
+}
+
+impl RegionMap {
+    /// Classify the image: PSP firmware from the PSP directories, compressed and
+    /// executable FFS sections from the volumes, then padding / strings / data by content,
+    /// with data in high-entropy blocks counted as compressed (or encrypted)
+    pub fn new(data: &[u8]) -> Self {
+        let mut structural: Vec<Option<RegionKind>> = vec![None; data.len().div_ceil(REGION_GRANULE)];
+        let mut paint = |start: usize, end: usize, kind: RegionKind| {
+            if start < end {
+                for granule in &mut structural[start / REGION_GRANULE..end.div_ceil(REGION_GRANULE)] {
+                    *granule = Some(kind);
+                }
+            }
+        };
+
+        // PSP firmware; BIOS directories point at the BIOS itself, so only PSP-level ones count
+        for dir in parse_psp_directories(data).iter().filter(|d| !d.signature.contains("BHD") && d.signature != "$BL2") {
+            for entry in &dir.entries {
+                let Some(offset) = entry.offset else { continue };
+                if entry.size != 0 && entry.size != u32::MAX {
+                    paint(offset, (offset + entry.size as usize).min(data.len()), RegionKind::Psp);
+                }
+            }
+        }
+
+        let volumes = parse_firmware_volumes(data);
+        for file in all_files(&volumes) {
+            for section in flatten_sections(&file.sections) {
+                let (start, end) = (section.data_offset(), section.offset + section.size);
+                match section.section_type {
+                    SECTION_COMPRESSION | SECTION_GUID_DEFINED
+                        if section.children.is_empty() && section.guid.as_deref() != Some(CRC32_SECTION_GUID) =>
+                        paint(start, end, RegionKind::Compressed),
+                    SECTION_PE32 | SECTION_PIC | SECTION_TE => match image_code_ranges(data, start, end) {
+                        Some(ranges) => ranges.into_iter().for_each(|(from, to)| paint(from, to, RegionKind::Code)),
+                        None => paint(start, end, RegionKind::Code),
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        let entropy = entropy_map(data);
+        let granules = structural.into_iter()
+            .zip(data.chunks(REGION_GRANULE))
+            .enumerate()
+            .map(|(i, (kind, bytes))| kind.unwrap_or_else(|| match classify_content(bytes) {
+                RegionKind::Data if entropy[i * REGION_GRANULE / ENTROPY_BLOCK] >= HIGH_ENTROPY => RegionKind::Compressed,
+                kind => kind,
+            }))
+            .collect();
+        RegionMap { granules, len: data.len(), entropy }
+    }
+
+    pub fn kind_at(&self, offset: usize) -> RegionKind {
+        self.granules.get(offset / REGION_GRANULE).copied().unwrap_or(RegionKind::Padding)
+    }
+
+    /// Merged runs of equal kind
+    pub fn regions(&self) -> Vec<Region> {
+        let mut regions: Vec<Region> = Vec::new();
+        for (i, &kind) in self.granules.iter().enumerate() {
+            let start = (i * REGION_GRANULE) as u64;
+            let end = ((i + 1) * REGION_GRANULE).min(self.len) as u64;
+            match regions.last_mut() {
+                Some(last) if last.kind == kind => last.end = end,
+                _ => regions.push(Region { start, end, kind }),
+            }
+        }
+        regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    /// PE/TE section header at `at`: raw size, raw pointer and characteristics
+    fn section_header(image: &mut [u8], at: usize, raw_size: u32, raw_pointer: u32, characteristics: u32) {
+        image[at + 16..at + 20].copy_from_slice(&raw_size.to_le_bytes());
+        image[at + 20..at + 24].copy_from_slice(&raw_pointer.to_le_bytes());
+        image[at + 36..at + 40].copy_from_slice(&characteristics.to_le_bytes());
+    }
+
+    /// 0x400-byte PE32 image: .text at 0x100..0x200, .data at 0x200..0x300
+    fn pe_image() -> Vec<u8> {
+        let mut image = vec![0u8; 0x400];
+        image[..2].copy_from_slice(b"MZ");
+        image[0x3C..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        image[0x40..0x44].copy_from_slice(b"PE\0\0");
+        image[0x46..0x48].copy_from_slice(&2u16.to_le_bytes());
+        section_header(&mut image, 0x58, 0x100, 0x100, 0x6000_0020);
+        section_header(&mut image, 0x80, 0x100, 0x200, 0xC000_0040);
+        image
+    }
+
+    #[test]
+    fn image_code_ranges_pe32() {
+        let image = pe_image();
+        assert_eq!(image_code_ranges(&image, 0, image.len()), Some(vec![(0x100, 0x200)]));
+        // Ranges are image offsets and clipped to the section
+        let data = [vec![0; 0x10], image].concat();
+        assert_eq!(image_code_ranges(&data, 0x10, 0x190), Some(vec![(0x110, 0x190)]));
+        assert_eq!(image_code_ranges(&[0; 0x40], 0, 0x40), None);
+    }
+
+    #[test]
+    fn image_code_ranges_te_reads_section_count_at_4() {
+        // NumberOfSections 2 at offset 4, Subsystem 0x0B at 5, StrippedSize 0x1E8 at 6
+        let mut image = vec![0u8; 0x300];
+        image[..2].copy_from_slice(b"VZ");
+        image[4] = 2;
+        image[5] = 0x0B;
+        image[6..8].copy_from_slice(&0x1E8u16.to_le_bytes());
+        section_header(&mut image, 40, 0x20, 0x300, 0xC000_0040);
+        section_header(&mut image, 80, 0x80, 0x240, 0x6000_0020);
+        // Raw pointers are relative to the stripped header: 0x240 + 40 - 0x1E8 = 0x80
+        assert_eq!(image_code_ranges(&image, 0, image.len()), Some(vec![(0x80, 0x100)]));
+    }
+
+    #[test]
+    fn classify_content_by_bytes() {
+        assert_eq!(classify_content(&[0xFF; 0x100]), RegionKind::Padding);
+        assert_eq!(classify_content(&[0; 0x100]), RegionKind::Padding);
+        let text: Vec<u8> = b"Memory Clock Speed\0Fan Control\0".iter().copied().cycle().take(0x100).collect();
+        assert_eq!(classify_content(&text), RegionKind::Strings);
+        // Digits and punctuation are text but not words
+        let numbers: Vec<u8> = b"0123456789,".iter().copied().cycle().take(0x100).collect();
+        assert_eq!(classify_content(&numbers), RegionKind::Data);
+        let table: Vec<u8> = [0x10, 0x00, 0x20, 0x00].iter().copied().cycle().take(0x100).collect();
+        assert_eq!(classify_content(&table), RegionKind::Data);
+    }
+
+    #[test]
+    fn region_map_kinds() {
+        let table: Vec<u8> = [0x10, 0x00, 0x20, 0x00].iter().copied().cycle().take(0x200).collect();
+        let files = [
+            ffs_file(0x11, &section(SECTION_PE32, &pe_image())),
+            ffs_file(0x22, &section(SECTION_COMPRESSION, &[0x5A; 0x200])),
+            ffs_file(0x33, &section(SECTION_RAW, &table)),
+        ];
+        let data = volume(&files, 0x2000);
+        let map = RegionMap::new(&data);
+
+        // PE32 data starts at 0x48 + 0x18 + 4; .text covers 0x164..0x264
+        assert_eq!(map.kind_at(0x180), RegionKind::Code);
+        assert_eq!(map.kind_at(0x260), RegionKind::Code);
+        // Zero-filled .data is not code and falls back to content
+        assert_eq!(map.kind_at(0x380), RegionKind::Padding);
+        let compressed = 0x48 + 0x418 + 0x18 + 4;
+        assert_eq!(map.kind_at(compressed + 0x100), RegionKind::Compressed);
+        let raw = compressed + 0x200 + 0x18 + 4;
+        assert_eq!(map.kind_at(raw + 0x100), RegionKind::Data);
+        assert_eq!(map.kind_at(0x1F00), RegionKind::Padding);
+        assert_eq!(map.kind_at(0x10_0000), RegionKind::Padding);
+
+        let regions = map.regions();
+        assert_eq!((regions[0].start, regions.last().unwrap().end), (0, 0x2000));
+        assert!(regions.windows(2).all(|w| w[0].end == w[1].start && w[0].kind != w[1].kind));
+    }
+}
@@ -0,0 +1,265 @@
+//! LPDDR5 SPD decoder - JEDEC base section, timings and manufacturing data
+
+use crate::analysis::*;
+use crate::structures::*;
+
+/// Bytes needed to reach the end of the manufacturing section
+pub const SPD_MIN_SIZE: usize = 384;
+/// Base section covered by the CRC, CRC stored little-endian right after it
+pub const SPD_CRC_OFFSET: usize = 126;
+/// Base section length including the CRC
+pub const SPD_BASE_SIZE: usize = 128;
+/// DRAM device type byte (byte 2) for LPDDR5
+const SPD_TYPE_LPDDR5: u8 = 0x13;
+
+/// SDRAM density per die (byte 4, bits 3:0) in Mbit
+const DIE_DENSITY_MBIT: &[u32] = &[256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 12288, 24576, 3072, 6144, 18432];
+
+/// CAS latency for each bit of bytes 20..22 (byte 23 is reserved)
+const CAS_LATENCY_BITS: [[u32; 8]; 3] = [
+    [3, 6, 8, 9, 10, 11, 12, 14],
+    [16, 18, 20, 22, 24, 26, 28, 30],
+    [32, 34, 36, 40, 0, 0, 0, 0],
+];
+
+/// JEDEC SPD CRC16 (CCITT polynomial 0x1021, initial value 0) over bytes 0..126
+pub fn spd_crc(spd: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in &spd[..SPD_CRC_OFFSET] {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Stored CRC of the SPD base section at `offset` matches its contents
+pub fn spd_crc_valid(data: &[u8], offset: usize) -> bool {
+    data.get(offset..offset + SPD_BASE_SIZE)
+        .is_some_and(|spd| read_u16(spd, SPD_CRC_OFFSET) == Some(spd_crc(spd)))
+}
+
+/// JEDEC JEP106 IDs, continuation byte first
+const JEDEC_MANUFACTURERS: &[(u16, &str)] = &[
+    (0x80CE, "Samsung"),
+    (0x802C, "Micron"),
+    (0x80AD, "SK hynix"),
+];
+
+/// Part-number prefixes, for SPDs with an empty manufacturer ID
+const PART_PREFIXES: &[(&str, &str)] = &[
+    ("K3L", "Samsung"),
+    ("MT6", "Micron"),
+    ("H58", "SK hynix"),
+];
+
+/// Memory parts known to ship in the Steam Deck
+const KNOWN_PARTS: &[(&str, &str)] = &[
+    ("K3LKBKB0BM-MGCP", "Samsung LPDDR5-6400, 16 GB Jupiter (LCD)"),
+];
+
+pub fn manufacturer_name(id: u16) -> Option<&'static str> {
+    JEDEC_MANUFACTURERS.iter().find(|(i, _)| *i == id).map(|(_, n)| *n)
+}
+
+/// Manufacturer from the DRAM ID, then the module ID, then the part-number prefix
+fn identify_manufacturer(dram: u16, module: u16, part: &str) -> Option<String> {
+    manufacturer_name(dram)
+        .or_else(|| manufacturer_name(module))
+        .or_else(|| PART_PREFIXES.iter().find(|(p, _)| part.starts_with(p)).map(|(_, n)| *n))
+        .map(str::to_string)
+}
+
+fn known_part(part: &str) -> Option<String> {
+    KNOWN_PARTS.iter().find(|(p, _)| *p == part).map(|(_, d)| d.to_string())
+}
+
+/// Geometry summary used to tell memory configurations apart
+pub fn spd_configuration(info: &SpdInfo) -> String {
+    format!("{}Gb/die x{} {}R {} MT/s", info.die_density_mbit / 1024, info.device_width, info.ranks, info.max_data_rate)
+}
+
+/// Group SPD entries by manufacturer, part number and configuration
+pub fn group_spds(spds: &[SpdStructure]) -> Vec<SpdGroup> {
+    let mut groups: Vec<SpdGroup> = Vec::new();
+    for spd in spds {
+        let (manufacturer, part_number, configuration) = match &spd.decoded {
+            Some(info) => (
+                info.manufacturer.clone().unwrap_or_else(|| "unknown".to_string()),
+                info.part_number.clone(),
+                spd_configuration(info),
+            ),
+            None => ("unknown".to_string(), String::new(), format!("undecoded ({})", spd.vendor)),
+        };
+        match groups.iter_mut().find(|g| g.manufacturer == manufacturer
+            && g.part_number == part_number && g.configuration == configuration) {
+            Some(group) => group.entries.push(spd.offset),
+            None => groups.push(SpdGroup {
+                manufacturer,
+                part_number,
+                configuration,
+                entries: vec![spd.offset],
+            }),
+        }
+    }
+    groups
+}
+
+/// Timing in ps from an MTB byte and its signed FTB correction
+fn timing_ps(mtb: u32, value: u32, fine: u8) -> u32 {
+    (mtb as i64 * value as i64 + fine as i8 as i64).max(0) as u32
+}
+
+/// Trimmed ASCII field, unused bytes are blanks or zeros
+fn ascii_field(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Decode the SPD block at `offset`; None unless it is a complete LPDDR5 SPD
+pub fn decode_spd(data: &[u8], offset: usize) -> Option<SpdInfo> {
+    let spd = data.get(offset..offset + SPD_MIN_SIZE)?;
+    if spd[2] != SPD_TYPE_LPDDR5 {
+        return None;
+    }
+    // Byte 17: only MTB 0.125 ns / FTB 1 ps are defined
+    if spd[17] & 0x0F != 0 {
+        return None;
+    }
+    let (mtb, ftb) = (125u32, 1u32);
+
+    let bytes_used = match spd[0] & 0x0F {
+        n @ 1..=4 => 128 * n as usize,
+        _ => 0,
+    };
+    let bytes_total = match (spd[0] >> 4) & 0x07 {
+        1 => 256,
+        2 => 512,
+        _ => 0,
+    };
+
+    let die_density_mbit = *DIE_DENSITY_MBIT.get((spd[4] & 0x0F) as usize)?;
+    let channels_per_die = 1u32 << ((spd[6] >> 2) & 0x03);
+
+    let cas_latencies = CAS_LATENCY_BITS.iter()
+        .zip(&spd[20..23])
+        .flat_map(|(cls, &mask)| (0..8).filter(move |b| mask & (1 << b) != 0).map(move |b| cls[b]))
+        .filter(|&cl| cl != 0)
+        .collect();
+
+    let tck_min_ps = timing_ps(mtb, spd[18] as u32, spd[125]);
+    let module_manufacturer = u16::from_be_bytes([spd[320], spd[321]]);
+    let dram_manufacturer = u16::from_be_bytes([spd[350], spd[351]]);
+    let part_number = ascii_field(&spd[329..349]);
+
+    Some(SpdInfo {
+        revision: format!("{}.{}", spd[1] >> 4, spd[1] & 0x0F),
+        bytes_used,
+        bytes_total,
+        die_density_mbit,
+        channel_density_mbit: die_density_mbit / channels_per_die,
+        banks: 4 << ((spd[4] >> 4) & 0x03),
+        bank_groups: 1 << (spd[4] >> 6),
+        rows: 12 + ((spd[5] >> 3) & 0x07) as u32,
+        columns: 9 + (spd[5] & 0x07) as u32,
+        monolithic: spd[6] & 0x80 == 0,
+        dies: 1 + ((spd[6] >> 4) & 0x07) as u32,
+        channels_per_die,
+        ranks: 1 + ((spd[12] >> 3) & 0x07) as u32,
+        device_width: 4 << (spd[12] & 0x07),
+        bus_width: 8 << (spd[13] & 0x07),
+        mtb_ps: mtb,
+        ftb_ps: ftb,
+        tck_min_ps,
+        tck_max_ps: timing_ps(mtb, spd[19] as u32, spd[124]),
+        max_data_rate: 8_000_000u32.checked_div(tck_min_ps).unwrap_or(0),
+        cas_latencies,
+        taa_ps: timing_ps(mtb, spd[24] as u32, spd[123]),
+        trcd_ps: timing_ps(mtb, spd[26] as u32, spd[122]),
+        trpab_ps: timing_ps(mtb, spd[27] as u32, spd[121]),
+        trppb_ps: timing_ps(mtb, spd[28] as u32, spd[120]),
+        trfcab_ps: mtb * read_u16(spd, 29)? as u32,
+        trfcpb_ps: mtb * read_u16(spd, 31)? as u32,
+        module_manufacturer,
+        dram_manufacturer,
+        manufacturer: identify_manufacturer(dram_manufacturer, module_manufacturer, &part_number),
+        known_part: known_part(&part_number),
+        part_number,
+        crc: read_u16(spd, SPD_CRC_OFFSET)?,
+        crc_valid: read_u16(spd, SPD_CRC_OFFSET)? == spd_crc(spd),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    #[test]
+    fn spd_crc_known_answer() {
+        let spd = mgcp_spd();
+        // CRC-16/XMODEM of bytes 0..126 (Python binascii.crc_hqx(data, 0))
+        assert_eq!(spd_crc(&spd), 0x2707);
+        // mgcp.txt stores 60 3F, which is not the JEDEC CRC of these bytes
+        assert!(!spd_crc_valid(&spd, 0));
+        assert!(!decode_spd(&spd, 0).unwrap().crc_valid);
+
+        let mut fixed = spd.clone();
+        fixed[SPD_CRC_OFFSET..SPD_BASE_SIZE].copy_from_slice(&0x2707u16.to_le_bytes());
+        assert!(spd_crc_valid(&fixed, 0));
+        assert!(decode_spd(&fixed, 0).unwrap().crc_valid);
+    }
+
+    #[test]
+    fn decode_spd_mgcp() {
+        let spd = mgcp_spd();
+        assert_eq!(spd.len(), 512);
+        let info = decode_spd(&spd, 0).unwrap();
+        assert_eq!((info.bytes_used, info.bytes_total), (384, 512));
+        assert_eq!(info.revision, "1.0");
+        assert_eq!((info.ranks, info.device_width, info.bus_width), (1, 16, 16));
+        assert_eq!((info.dies, info.channels_per_die, info.monolithic), (2, 2, false));
+        // mgcp.txt comments say 8 Gb and 15 rows; the JEDEC encodings of bytes 4/5 are 16 Gb and 16 rows
+        assert_eq!((info.die_density_mbit, info.channel_density_mbit), (16384, 8192));
+        assert_eq!((info.rows, info.columns, info.banks, info.bank_groups), (16, 11, 8, 1));
+        assert_eq!((info.mtb_ps, info.ftb_ps), (125, 1));
+        assert_eq!((info.tck_min_ps, info.max_data_rate), (1250, 6400));
+        assert_eq!(info.tck_max_ps, 32_002);
+        assert_eq!(info.cas_latencies, vec![6, 10, 14, 16, 20, 24, 28, 32, 36]);
+        assert_eq!((info.taa_ps, info.trcd_ps), (21_250, 18_000));
+        assert_eq!((info.trpab_ps, info.trppb_ps), (21_000, 18_000));
+        assert_eq!((info.trfcab_ps, info.trfcpb_ps), (280_000, 140_000));
+        assert_eq!((info.module_manufacturer, info.dram_manufacturer), (0x80CE, 0x80CE));
+        assert_eq!(info.part_number, "K3LKBKB0BM-MGCP");
+        assert_eq!(info.crc, 0x3F60);
+    }
+
+    #[test]
+    fn decode_spd_rejects_other_types() {
+        let mut spd = mgcp_spd();
+        spd[2] = 0x0C;
+        assert!(decode_spd(&spd, 0).is_none());
+        assert!(decode_spd(&mgcp_spd()[..SPD_MIN_SIZE - 1], 0).is_none());
+    }
+
+    #[test]
+    fn identify_mgcp_vendor_and_part() {
+        let info = decode_spd(&mgcp_spd(), 0).unwrap();
+        assert_eq!(info.manufacturer.as_deref(), Some("Samsung"));
+        assert!(info.known_part.is_some());
+
+        // Without JEDEC IDs the part-number prefix decides
+        let mut spd = mgcp_spd();
+        spd[320..322].copy_from_slice(&[0, 0]);
+        spd[350..352].copy_from_slice(&[0, 0]);
+        assert_eq!(decode_spd(&spd, 0).unwrap().manufacturer.as_deref(), Some("Samsung"));
+        spd[329..332].copy_from_slice(b"XYZ");
+        assert_eq!(decode_spd(&spd, 0).unwrap().manufacturer, None);
+        assert_eq!(manufacturer_name(0x802C), Some("Micron"));
+    }
+}
@@ -1,5 +1,7 @@
 //! Data structures for BIOS analysis
 
+use crate::entropy::entropy_summary;
+use crate::regions::RegionKind;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
@@ -7,16 +9,27 @@ use std::collections::BTreeMap;
 pub struct BiosReport {
     pub filename: String,
     pub size: usize,
+    pub identity: Option<BiosIdentity>,
+    pub identification: Option<BiosIdentification>,
     pub uefi_volumes: Vec<UefiVolume>,
+    pub regions: Vec<Region>,
+    /// Shannon entropy (bits per byte) of every 4 KiB block
+    pub entropy: Vec<f64>,
     pub spd_structures: Vec<SpdStructure>,
+    pub spd_groups: Vec<SpdGroup>,
     pub frequency_tables: Vec<FrequencyTable>,
+    pub voltage_settings: Vec<VoltageSetting>,
     pub power_structures: Vec<PowerStructure>,
+    pub power_limits: Vec<PowerLimit>,
+    /// Raw power values that are not inside a located power-limit structure
+    pub coincidental_power_matches: usize,
     pub smu_info: Vec<SmuInfo>,
     pub strings: BTreeMap<String, Vec<u64>>,
     pub guids: Vec<GuidInfo>,
     pub numeric_tables: Vec<NumericTable>,
     pub psp_entries: Vec<PspEntry>,
     pub ec_info: Vec<EcInfo>,
+    pub mirrors: Vec<MirrorRegion>,
     pub patches: Vec<PatchCandidate>,
 }
 
@@ -25,16 +38,25 @@ impl BiosReport {
         Self {
             filename: filename.to_string(),
             size,
+            identity: None,
+            identification: None,
             uefi_volumes: Vec::new(),
+            regions: Vec::new(),
+            entropy: Vec::new(),
             spd_structures: Vec::new(),
+            spd_groups: Vec::new(),
             frequency_tables: Vec::new(),
+            voltage_settings: Vec::new(),
             power_structures: Vec::new(),
+            power_limits: Vec::new(),
+            coincidental_power_matches: 0,
             smu_info: Vec::new(),
             strings: BTreeMap::new(),
             guids: Vec::new(),
             numeric_tables: Vec::new(),
             psp_entries: Vec::new(),
             ec_info: Vec::new(),
+            mirrors: Vec::new(),
             patches: Vec::new(),
         }
     }
@@ -42,6 +64,28 @@ impl BiosReport {
     pub fn print(&self) {
         use colored::*;
         
+        // Identity
+        println!("\n{}", "═".repeat(80).cyan());
+        println!("{}", " IDENTITY".bold().yellow());
+        println!("{}", "═".repeat(80).cyan());
+        if let Some(identity) = &self.identity {
+            println!("  Build:   {} ({})", identity.build_date.as_deref().unwrap_or("unknown"),
+                identity.board.as_deref().unwrap_or("board unknown"));
+            println!("  AGESA:   {}", identity.agesa.as_deref().unwrap_or("unknown"));
+            if let Some(bios) = &identity.smbios_bios {
+                println!("  SMBIOS0: {} {} {}", bios.vendor, bios.version, bios.release_date);
+            }
+            if let Some(system) = &identity.smbios_system {
+                println!("  SMBIOS1: {} {} {} {}", system.manufacturer, system.product, system.version, system.family);
+            }
+        }
+        if let Some(id) = &self.identification {
+            println!("  Version: {} ({}, {})", id.version.as_deref().unwrap_or("unknown"),
+                id.platform.as_deref().unwrap_or("unknown"), id.kind);
+            println!("  Match:   {} (score {})", id.match_type, id.score);
+            println!("  SHA-256: {}", id.sha256);
+        }
+        
         // UEFI Volumes
         println!("\n{}", "═".repeat(80).cyan());
         println!("{}", " 1. UEFI VOLUMES".bold().yellow());
@@ -50,15 +94,55 @@ impl BiosReport {
             println!("  {} @ 0x{:08X} - Size: 0x{:X}, Type: {}", 
                 "Volume".green(), vol.offset, vol.size, vol.vol_type);
         }
+        if !self.regions.is_empty() {
+            println!("\n  {}", "Regions:".bold());
+            for kind in RegionKind::ALL {
+                let bytes: u64 = self.regions.iter().filter(|r| r.kind == kind).map(|r| r.end - r.start).sum();
+                println!("    {:<10} 0x{:08X} bytes ({:.1}%), {} runs", kind.name(), bytes,
+                    bytes as f64 * 100.0 / self.size.max(1) as f64,
+                    self.regions.iter().filter(|r| r.kind == kind).count());
+            }
+        }
+        if !self.entropy.is_empty() {
+            println!("\n  {}", "Entropy (4 KiB blocks):".bold());
+            for (band, blocks) in entropy_summary(&self.entropy) {
+                println!("    {:<24} {} blocks", band, blocks);
+            }
+        }
         
         // SPD
         println!("\n{}", "═".repeat(80).cyan());
         println!("{}", " 2. SPD STRUCTURES (Memory)".bold().yellow());
         println!("{}", "═".repeat(80).cyan());
         for spd in &self.spd_structures {
-            let status = if spd.locked { "LOCKED".red() } else { "UNLOCKED".green() };
-            println!("  @ 0x{:08X}: tCK=0x{:02X} [{}] vendor={}", 
-                spd.offset, spd.tck, status, spd.vendor);
+            let vendor = spd.decoded.as_ref()
+                .and_then(|d| d.manufacturer.clone())
+                .unwrap_or_else(|| "unknown".to_string());
+            println!("  @ 0x{:08X}: {} ({}){}", spd.offset, vendor, spd.vendor,
+                if spd.decoded.is_none() { " - not a decodable LPDDR5 SPD" } else { "" });
+            if let Some(info) = &spd.decoded {
+                let crc = if info.crc_valid { "CRC OK".green() } else { "CRC BAD".red() };
+                println!("      {} [{}] {}Gb/die x{} {}R, tCK={:.3}ns ({} MT/s), tAA={:.2} tRCD={:.2} tRPab={:.2} tRPpb={:.2} tRFCab={:.1}ns",
+                    if info.part_number.is_empty() { "-" } else { &info.part_number }, crc,
+                    info.die_density_mbit / 1024, info.device_width, info.ranks,
+                    info.tck_min_ps as f64 / 1000.0, info.max_data_rate,
+                    info.taa_ps as f64 / 1000.0, info.trcd_ps as f64 / 1000.0,
+                    info.trpab_ps as f64 / 1000.0, info.trppb_ps as f64 / 1000.0,
+                    info.trfcab_ps as f64 / 1000.0);
+                if let Some(known) = &info.known_part {
+                    println!("      {}", known.green());
+                }
+            }
+        }
+        if !self.spd_groups.is_empty() {
+            println!("\n  {}", "By vendor / part:".bold());
+            for group in &self.spd_groups {
+                let entries: Vec<String> = group.entries.iter().map(|o| format!("0x{:X}", o)).collect();
+                println!("  {} {} [{}]: {} entr{} {}", group.manufacturer,
+                    if group.part_number.is_empty() { "-" } else { &group.part_number },
+                    group.configuration, group.entries.len(),
+                    if group.entries.len() == 1 { "y" } else { "ies" }, entries.join(", "));
+            }
         }
         
         // Frequency Tables
@@ -67,13 +151,48 @@ impl BiosReport {
         println!("{}", "═".repeat(80).cyan());
         for ft in &self.frequency_tables {
             println!("  @ 0x{:08X}: {:?}", ft.offset, ft.values);
+            for c in &ft.codes {
+                let clock = match (c.mhz, c.data_rate) {
+                    (Some(mhz), Some(rate)) => format!("{} MHz / {} MT/s", mhz, rate),
+                    _ => "-".to_string(),
+                };
+                let status = if c.exposed { "menu".green() } else { "hidden".yellow() };
+                println!("      0x{:02X}: {:<22} [{}] {}", c.code, clock, status, c.note);
+            }
+            if !ft.hidden.is_empty() {
+                let hidden: Vec<String> = ft.hidden.iter().map(|c| format!("0x{:02X}", c)).collect();
+                println!("      hidden: {}", hidden.join(", "));
+            }
+        }
+        if !self.voltage_settings.is_empty() {
+            println!("\n  {}", "Memory voltage indices:".bold());
+            for v in &self.voltage_settings {
+                let volts = |mv: Option<f64>| mv.map_or(String::new(), |mv| format!(" (~{:.3} V)", mv / 1000.0));
+                let default = v.default.map_or("-".to_string(), |d| format!("{}{}", d, volts(v.default_mv)));
+                let safe = v.safe.map_or("no documented limit".to_string(), |(min, max)| format!("safe {}..{}", min, max));
+                println!("  @ 0x{:08X}: {} '{}' {}+0x{:04X} default {} range {}{}..{}{} [{}; {}]",
+                    v.offset, v.rail, v.prompt, v.varstore.as_deref().unwrap_or("-"), v.var_offset, default,
+                    v.min, volts(v.min_mv), v.max, volts(v.max_mv), safe, v.scale_source);
+                for w in &v.warnings {
+                    println!("      {} {}", "warning".yellow(), w);
+                }
+            }
         }
         
         // Power
         println!("\n{}", "═".repeat(80).cyan());
         println!("{}", " 4. POWER MANAGEMENT".bold().yellow());
         println!("{}", "═".repeat(80).cyan());
-        for pw in &self.power_structures {
+        for limit in &self.power_limits {
+            let value = if limit.value == 0 { "Auto".to_string() } else { format!("{} {}", limit.value, limit.unit) };
+            println!("  {:<9} {:<10} {} @ 0x{:08X} ('{}', {}+0x{:04X})", limit.name, value, limit.source,
+                limit.offset, limit.prompt, limit.varstore.as_deref().unwrap_or("-"), limit.var_offset);
+        }
+        if !self.power_structures.is_empty() {
+            println!("  {}", format!("Raw value matches at a power limit ({} coincidental matches rejected):",
+                self.coincidental_power_matches).dimmed());
+        }
+        for pw in self.power_structures.iter().filter(|p| p.backed) {
             println!("  @ 0x{:08X}: {}W ({}mW) - {}", 
                 pw.offset, pw.watts, pw.milliwatts, pw.description);
         }
@@ -86,6 +205,16 @@ impl BiosReport {
             println!("  @ 0x{:08X}: {}", smu.offset, smu.description);
         }
         
+        // Mirrors
+        println!("\n{}", "═".repeat(80).cyan());
+        println!("{}", " 6. MIRROR REGIONS (A/B)".bold().yellow());
+        println!("{}", "═".repeat(80).cyan());
+        for m in &self.mirrors {
+            let status = if m.divergences.is_empty() { "IDENTICAL".green() } else { "DIVERGED".red() };
+            println!("  0x{:08X} <-> 0x{:08X} (+0x{:X}), 0x{:X} bytes [{}] {} divergence(s)",
+                m.primary, m.mirror, m.delta, m.length, status, m.divergences.len());
+        }
+
         // Patches
         println!("\n{}", "═".repeat(80).cyan());
         println!("{}", " PATCH CANDIDATES".bold().green());
@@ -103,6 +232,54 @@ impl BiosReport {
     }
 }
 
+/// Version, build and platform strings found in the image
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BiosIdentity {
+    pub version: Option<String>,
+    pub build_date: Option<String>,
+    pub agesa: Option<String>,
+    /// "Jupiter" (LCD) or "Galileo" (OLED)
+    pub platform: Option<String>,
+    pub board: Option<String>,
+    /// ASCII + UTF-16 occurrences of each platform codename
+    pub codenames: BTreeMap<String, usize>,
+    pub smbios_bios: Option<SmbiosBios>,
+    pub smbios_system: Option<SmbiosSystem>,
+}
+
+/// SMBIOS type 0 defaults
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SmbiosBios {
+    pub offset: u64,
+    pub vendor: String,
+    pub version: String,
+    pub release_date: String,
+}
+
+/// SMBIOS type 1 defaults
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SmbiosSystem {
+    pub offset: u64,
+    pub manufacturer: String,
+    pub product: String,
+    pub version: String,
+    pub sku: String,
+    pub family: String,
+}
+
+/// Release the image was matched to in the known-BIOS database
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BiosIdentification {
+    pub sha256: String,
+    pub version: Option<String>,
+    pub platform: Option<String>,
+    pub kind: String,
+    /// "exact", "modified" (known hash differs), "unverified" (no hash known) or "unknown"
+    pub match_type: String,
+    pub score: u32,
+    pub notes: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UefiVolume {
     pub offset: u64,
@@ -114,10 +291,87 @@ pub struct UefiVolume {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SpdStructure {
     pub offset: u64,
+    /// Bytes 4..7 (density, addressing, package, features), not a manufacturer ID
     pub vendor: String,
-    pub tck: u8,
-    pub locked: bool,
+    /// Base section (bytes 0..128)
     pub raw: String,
+    pub decoded: Option<SpdInfo>,
+}
+
+/// JEDEC LPDDR5 SPD contents; timings in picoseconds
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SpdInfo {
+    pub revision: String,
+    pub bytes_used: usize,
+    pub bytes_total: usize,
+    pub die_density_mbit: u32,
+    pub channel_density_mbit: u32,
+    pub banks: u32,
+    pub bank_groups: u32,
+    pub rows: u32,
+    pub columns: u32,
+    pub monolithic: bool,
+    pub dies: u32,
+    pub channels_per_die: u32,
+    pub ranks: u32,
+    pub device_width: u32,
+    pub bus_width: u32,
+    pub mtb_ps: u32,
+    pub ftb_ps: u32,
+    pub tck_min_ps: u32,
+    pub tck_max_ps: u32,
+    /// MT/s at tCKmin (8000 / tCK in ns)
+    pub max_data_rate: u32,
+    pub cas_latencies: Vec<u32>,
+    pub taa_ps: u32,
+    pub trcd_ps: u32,
+    pub trpab_ps: u32,
+    pub trppb_ps: u32,
+    pub trfcab_ps: u32,
+    pub trfcpb_ps: u32,
+    /// JEDEC ID, continuation byte first (0x80CE = Samsung)
+    pub module_manufacturer: u16,
+    pub dram_manufacturer: u16,
+    pub manufacturer: Option<String>,
+    pub part_number: String,
+    /// Description of a part known to ship in the Steam Deck
+    pub known_part: Option<String>,
+    /// Stored CRC16 (bytes 126..127)
+    pub crc: u16,
+    pub crc_valid: bool,
+}
+
+/// SPD timings converted to clocks at one data rate
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimingSet {
+    pub part_number: String,
+    pub data_rate: u32,
+    pub tck_ps: u32,
+    /// Target rate is faster than the SPD tCKmin
+    pub over_spec: bool,
+    /// Lowest supported CAS latency covering tAA
+    pub cas_latency: Option<u32>,
+    pub timings: Vec<TimingValue>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimingValue {
+    pub name: String,
+    pub ns: f64,
+    pub clocks: u32,
+    /// "SPD", "JEDEC" or the formula it was derived with
+    pub source: String,
+    /// Hand-tuned value from the guide, when one exists for this rate
+    pub tuned: Option<u32>,
+}
+
+/// SPD entries sharing a manufacturer, part number and configuration
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SpdGroup {
+    pub manufacturer: String,
+    pub part_number: String,
+    pub configuration: String,
+    pub entries: Vec<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -125,6 +379,42 @@ pub struct FrequencyTable {
     pub offset: u64,
     pub values: Vec<u16>,
     pub table_type: String,
+    pub codes: Vec<MemClkCode>,
+    /// Codes in the table but not offered in the IFR menu
+    pub hidden: Vec<u16>,
+}
+
+/// One memory clock code of a frequency table
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemClkCode {
+    pub code: u16,
+    pub mhz: Option<u32>,
+    pub data_rate: Option<u32>,
+    /// Offered by the IFR "Memory Clock Speed" question
+    pub exposed: bool,
+    pub note: String,
+}
+
+/// IFR question that sets a memory rail by index, with its voltage where the help text gives a scale
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VoltageSetting {
+    pub offset: u64,
+    /// "VDDQ", "VDD2H" or "VDD2L"
+    pub rail: String,
+    pub prompt: String,
+    pub varstore: Option<String>,
+    pub var_offset: u16,
+    pub min: u64,
+    pub max: u64,
+    pub default: Option<u64>,
+    pub default_mv: Option<f64>,
+    pub min_mv: Option<f64>,
+    pub max_mv: Option<f64>,
+    /// Documented safe index range
+    pub safe: Option<(u64, u64)>,
+    /// Where the index-to-voltage scale comes from
+    pub scale_source: String,
+    pub warnings: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -133,6 +423,26 @@ pub struct PowerStructure {
     pub milliwatts: u32,
     pub watts: u32,
     pub description: String,
+    /// The value is a named power limit, not a coincidental match
+    pub backed: bool,
+}
+
+/// Named platform power limit read from a PBS/CBS question default or its setup variable
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PowerLimit {
+    /// "STAPM", "Fast PPT", "Slow PPT", "PPT", "TDC", "EDC" or "THM"
+    pub name: String,
+    /// In `unit`; 0 means Auto
+    pub value: u64,
+    /// "mW", "mA" or "C"
+    pub unit: String,
+    /// "ifr_default" or "nvram"; `offset` is where the value is stored
+    pub source: String,
+    pub offset: u64,
+    pub prompt: String,
+    pub varstore: Option<String>,
+    pub var_offset: u16,
+    pub size: usize,
 }
 
 
@@ -155,6 +465,15 @@ pub struct NumericTable {
     pub offset: u64,
     pub values: Vec<u32>,
     pub table_type: String,
+    pub region: RegionKind,
+}
+
+/// A run of image granules of one region kind
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub kind: RegionKind,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -172,6 +491,9 @@ pub struct EcInfo {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PatchCandidate {
+    /// Name of the recipe that produced this candidate
+    #[serde(default)]
+    pub recipe: String,
     pub offset: u64,
     pub original: Vec<u8>,
     pub patched: Vec<u8>,
@@ -179,3 +501,92 @@ pub struct PatchCandidate {
     pub effect: String,
     pub risk: String,
 }
+
+/// One patch as it was written into the output image
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppliedPatch {
+    pub offset: u64,
+    pub description: String,
+    #[serde(default)]
+    pub risk: String,
+    pub original: String,
+    pub patched: String,
+}
+
+/// Summary of a patch run, saved next to the patched image
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PatchSummary {
+    pub input: String,
+    pub output: String,
+    pub applied: Vec<AppliedPatch>,
+    pub checksums: Vec<ChecksumFix>,
+    pub bytes_changed: usize,
+}
+
+/// A checksum rewritten after patching
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChecksumFix {
+    pub offset: u64,
+    /// "ffs_header", "ffs_file", "fv_header", "crc32_section", "apcb" or "spd_crc"
+    pub kind: String,
+    pub name: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// One changed byte run in a patch file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffRecord {
+    pub offset: u64,
+    /// Hex bytes in the source image
+    pub original: String,
+    /// Hex bytes in the target image
+    pub patched: String,
+}
+
+/// Reversible diff between a stock and a patched image, identified by SHA-256
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PatchFile {
+    pub format: String,
+    pub description: String,
+    pub source_size: usize,
+    pub source_sha256: String,
+    pub target_size: usize,
+    pub target_sha256: String,
+    pub records: Vec<DiffRecord>,
+}
+
+/// A/B (primary/recovery) copy of a flash range
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MirrorRegion {
+    pub primary: u64,
+    pub mirror: u64,
+    pub length: u64,
+    pub delta: u64,
+    pub divergences: Vec<MirrorDivergence>,
+}
+
+/// Byte run that differs between a primary range and its mirror
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MirrorDivergence {
+    pub offset: u64,
+    pub mirror_offset: u64,
+    pub length: u64,
+}
+
+/// One finding of the flash-readiness check
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckItem {
+    pub name: String,
+    /// "pass", "warn" or "fail"
+    pub status: String,
+    pub detail: String,
+}
+
+/// Flash-readiness report, saved as `<image>.check.json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckReport {
+    pub image: String,
+    pub items: Vec<CheckItem>,
+    pub verdict: String,
+}
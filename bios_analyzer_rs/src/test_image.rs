@@ -0,0 +1,162 @@
+//! Test fixtures - minimal FV / FFS / section / HII images and the known-answer SPD from base/mgcp.txt
+
+use crate::ffs::*;
+
+/// Samsung K3LKBKB0BM-MGCP SPD as listed in base/mgcp.txt
+const MGCP_SPD: &str = include_str!("../../base/mgcp.txt");
+
+pub const SECTION_RAW: u8 = 0x19;
+const FILE_TYPE_DRIVER: u8 = 0x07;
+const FV_HEADER_SIZE: usize = 0x48;
+/// EFI_FVB2 attributes with ERASE_POLARITY set: free space is 0xFF
+const FV_ATTRIBUTES: u32 = 0x0004_FEFF;
+const FFS2_GUID: [u8; 16] = [
+    0x78, 0xE5, 0x8C, 0x8C, 0x3D, 0x8A, 0x1C, 0x4F, 0x99, 0x35, 0x89, 0x61, 0x85, 0xC3, 0x2D, 0xD3,
+];
+const CRC32_GUID: [u8; 16] = [
+    0xB0, 0xCD, 0x1B, 0xFC, 0x31, 0x7D, 0xAA, 0x49, 0x93, 0x6A, 0xA4, 0x60, 0x0D, 0x9D, 0xD0, 0x83,
+];
+
+/// The 512 SPD bytes of base/mgcp.txt; values before `///` comments, from the opening brace on
+pub fn mgcp_spd() -> Vec<u8> {
+    let body = &MGCP_SPD[MGCP_SPD.find('{').unwrap()..];
+    body.lines()
+        .flat_map(|line| line.split("///").next().unwrap().split(','))
+        .map(|token| token.trim_matches(|c: char| c.is_whitespace() || "{})".contains(c)))
+        .filter(|token| !token.is_empty())
+        .map(|token| match token.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16).unwrap(),
+            None => token.parse().unwrap(),
+        })
+        .collect()
+}
+
+/// Section with a 4-byte header, padded to 4 bytes
+pub fn section(section_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut bytes = ((body.len() + 4) as u32 | (section_type as u32) << 24).to_le_bytes().to_vec();
+    bytes.extend(body);
+    bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+    bytes
+}
+
+/// CRC32 GUID-defined section with a valid CRC over `body`
+pub fn crc32_section(body: &[u8]) -> Vec<u8> {
+    let mut header = CRC32_GUID.to_vec();
+    header.extend(28u16.to_le_bytes());
+    header.extend(0x02u16.to_le_bytes());
+    header.extend(crc32fast::hash(body).to_le_bytes());
+    header.extend(body);
+    section(SECTION_GUID_DEFINED, &header)
+}
+
+/// Driver file with FFS_ATTRIB_CHECKSUM and valid header and file checksums
+pub fn ffs_file(id: u8, sections: &[u8]) -> Vec<u8> {
+    let mut file = vec![id; 16];
+    file.extend([0, 0, FILE_TYPE_DRIVER, FFS_ATTRIB_CHECKSUM]);
+    file.extend(&((0x18 + sections.len()) as u32).to_le_bytes()[..3]);
+    file.push(0xF8);
+    file.extend(sections);
+    file[FFS_FILE_CHECKSUM] = sum8(&file[0x18..]).wrapping_neg();
+    let header_sum = sum8(&file[..0x18]).wrapping_sub(file[FFS_FILE_CHECKSUM]).wrapping_sub(file[FFS_STATE]);
+    file[FFS_HEADER_CHECKSUM] = header_sum.wrapping_neg();
+    file
+}
+
+/// Firmware volume of `length` bytes holding `files`, the rest erased
+pub fn volume(files: &[Vec<u8>], length: usize) -> Vec<u8> {
+    let mut fv = vec![0u8; FV_HEADER_SIZE];
+    fv[0x10..0x20].copy_from_slice(&FFS2_GUID);
+    fv[0x20..0x28].copy_from_slice(&(length as u64).to_le_bytes());
+    fv[0x28..0x2C].copy_from_slice(b"_FVH");
+    fv[0x2C..0x30].copy_from_slice(&FV_ATTRIBUTES.to_le_bytes());
+    fv[0x30..0x32].copy_from_slice(&(FV_HEADER_SIZE as u16).to_le_bytes());
+    fv[0x37] = 2;
+    fv[0x38..0x3C].copy_from_slice(&1u32.to_le_bytes());
+    fv[0x3C..0x40].copy_from_slice(&(length as u32).to_le_bytes());
+    let sum = sum16(&fv).wrapping_neg();
+    fv[FV_CHECKSUM..FV_CHECKSUM + 2].copy_from_slice(&sum.to_le_bytes());
+    for file in files {
+        fv.extend(file);
+        fv.resize(fv.len().div_ceil(8) * 8, 0xFF);
+    }
+    assert!(fv.len() <= length, "files do not fit the volume");
+    fv.resize(length, 0xFF);
+    fv
+}
+
+/// IFR opcode with the scope bit set when `scope`
+fn op(opcode: u8, scope: bool, body: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![opcode, (body.len() + 2) as u8 | if scope { 0x80 } else { 0 }];
+    bytes.extend(body);
+    bytes
+}
+
+/// HII package list with one form set ("Setup") holding the one-byte ONE_OF question
+/// "Memory Clock Speed" (Auto 0xFF default, 3200MHZ 0x59, 3733MHZ 0x5F) and its en-US strings
+pub fn hii_package_list() -> Vec<u8> {
+    let mut ops = op(0x05, true, &[2, 0, 3, 0, 0xD8, 0x01, 0x00, 0x50, 0x1F, 0x03, 0, 0x10, 0, 0xFF, 0]);
+    for (string, flags, value) in [(4, 0x10, 0xFF), (5, 0, 0x59), (6, 0, 0x5F)] {
+        ops.extend(op(0x09, false, &[string, 0, flags, 0, value]));
+    }
+    ops.extend(op(0x29, false, &[]));
+    package_list(ops, &["Setup", "Memory Clock Speed", "Help text", "Auto", "3200MHZ", "3733MHZ"])
+}
+
+/// HII package list with one form set ("Setup") holding a four-byte NUMERIC question with an
+/// EFI_IFR_DEFAULT per (prompt, help, default); setup variable offsets are 4 bytes apart
+pub fn hii_numeric_package_list(questions: &[(&str, &str, u32)]) -> Vec<u8> {
+    let mut strings = vec!["Setup"];
+    let mut ops = Vec::new();
+    for (i, &(prompt, help, default)) in questions.iter().enumerate() {
+        let id = strings.len() as u16 + 1;
+        strings.extend([prompt, help]);
+        let mut body = [id.to_le_bytes(), (id + 1).to_le_bytes(), (i as u16 + 1).to_le_bytes(),
+            0x5000u16.to_le_bytes(), (i as u16 * 4).to_le_bytes()].concat();
+        body.extend([0, 0x02]);
+        body.extend([0, u32::MAX, 1].into_iter().flat_map(u32::to_le_bytes));
+        ops.extend(op(0x07, true, &body));
+        ops.extend(op(0x5B, false, &[[0, 0, 0x02].as_slice(), &default.to_le_bytes()].concat()));
+        ops.extend(op(0x29, false, &[]));
+    }
+    package_list(ops, &strings)
+}
+
+/// Package list around `questions`: a form set with varstore "Setup" (id 0x5000) and one form,
+/// then the en-US string package with `texts` as ids 1, 2, ...
+fn package_list(questions: Vec<u8>, texts: &[&str]) -> Vec<u8> {
+    let mut ops = op(0x0E, true, &[[0xAA; 16].as_slice(), &[1, 0, 0, 0, 0, 0]].concat());
+    ops.extend(op(0x24, false, &[[0x99; 16].as_slice(), &[0x00, 0x50, 0x00, 0x04], b"Setup\0"].concat()));
+    ops.extend(op(0x01, true, &[1, 0, 1, 0]));
+    ops.extend(questions);
+    for _ in 0..2 {
+        ops.extend(op(0x29, false, &[]));
+    }
+    let mut forms = ((ops.len() + 4) as u32 | 0x02 << 24).to_le_bytes().to_vec();
+    forms.extend(ops);
+
+    let mut strings = vec![0u8; 53];
+    strings[4..8].copy_from_slice(&53u32.to_le_bytes());
+    strings[8..12].copy_from_slice(&53u32.to_le_bytes());
+    strings[46..52].copy_from_slice(b"en-US\0");
+    for text in texts {
+        strings.push(0x14);
+        strings.extend(text.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+    }
+    strings.push(0);
+    let length = (strings.len() as u32 | 0x04 << 24).to_le_bytes();
+    strings[..4].copy_from_slice(&length);
+
+    let packages = [forms, strings, vec![4, 0, 0, 0xDF]].concat();
+    let mut list = vec![0x12; 16];
+    list.extend(((packages.len() + 20) as u32).to_le_bytes());
+    list.extend(packages);
+    list
+}
+
+/// Path in the system temp directory, unique per test process
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("bios_analyzer_{}_{}", std::process::id(), name))
+        .to_string_lossy()
+        .into_owned()
+}
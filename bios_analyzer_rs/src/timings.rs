@@ -0,0 +1,195 @@
+//! LPDDR5 timing calculator - SPD nanosecond timings to CK clock counts for a target data rate
+
+use crate::analysis::*;
+use crate::patterns::*;
+use crate::spd::*;
+use crate::structures::*;
+use colored::Colorize;
+use memmap2::Mmap;
+use std::fs::File;
+
+/// Data rates compared when `--rate` is not given
+const DEFAULT_RATES: &[u32] = &[6400, 6933, 7066];
+
+/// JEDEC LPDDR5 tRASmin, not stored in the SPD
+const TRAS_MIN_PS: u32 = 42_000;
+
+/// Hand-tuned Samsung values @ 6400 MT/s from the @noncatt guide (BIOS_SECRETS.md, section 5)
+const TUNED_6400: &[(&str, u32)] = &[
+    ("tRCD", 0x0C),
+    ("tRPpb", 0x0B),
+    ("tRAS", 0x15),
+    ("tRC", 0x20),
+];
+
+/// CK period in ps: LPDDR5 transfers 8 words per CK (tCK = 8000 / rate ns)
+pub fn tck_ps_for_rate(rate: u32) -> u32 {
+    8_000_000 / rate
+}
+
+/// JEDEC rounding: clocks = (t * 1000 / tCK + 974) / 1000, i.e. a 2.5% guard band before rounding up
+pub fn to_clocks(t_ps: u32, tck_ps: u32) -> u32 {
+    ((t_ps as u64 * 1000 / tck_ps as u64 + 974) / 1000) as u32
+}
+
+/// Convert every SPD timing of `info` to clock counts at `rate` MT/s
+pub fn calculate_timings(info: &SpdInfo, rate: u32) -> TimingSet {
+    let tck_ps = tck_ps_for_rate(rate);
+    let tras_ps = TRAS_MIN_PS;
+    let params = [
+        ("tAA", info.taa_ps, "SPD"),
+        ("tRCD", info.trcd_ps, "SPD"),
+        ("tRPab", info.trpab_ps, "SPD"),
+        ("tRPpb", info.trppb_ps, "SPD"),
+        ("tRAS", tras_ps, "JEDEC"),
+        ("tRC", tras_ps + info.trpab_ps, "tRAS + tRPab"),
+        ("tRFCab", info.trfcab_ps, "SPD"),
+        ("tRFCpb", info.trfcpb_ps, "SPD"),
+    ];
+    let timings = params.iter()
+        .map(|&(name, t_ps, source)| TimingValue {
+            name: name.to_string(),
+            ns: t_ps as f64 / 1000.0,
+            clocks: to_clocks(t_ps, tck_ps),
+            source: source.to_string(),
+            tuned: if rate == 6400 {
+                TUNED_6400.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+            } else {
+                None
+            },
+        })
+        .collect();
+    let taa_clocks = to_clocks(info.taa_ps, tck_ps);
+    TimingSet {
+        part_number: info.part_number.clone(),
+        data_rate: rate,
+        tck_ps,
+        over_spec: tck_ps < info.tck_min_ps,
+        cas_latency: info.cas_latencies.iter().copied().find(|&cl| cl >= taa_clocks),
+        timings,
+    }
+}
+
+pub fn print_timing_set(set: &TimingSet) {
+    let header = format!("  {} @ {} MT/s (tCK {:.4} ns)",
+        if set.part_number.is_empty() { "-" } else { &set.part_number },
+        set.data_rate, set.tck_ps as f64 / 1000.0);
+    println!("\n{}", header.bold());
+    if set.over_spec {
+        println!("    {}", "above the SPD tCKmin: JEDEC values are only a starting point".yellow());
+    }
+    match set.cas_latency {
+        Some(cl) => println!("    CL {}", cl),
+        None => println!("    CL {}", "none of the supported latencies covers tAA".red()),
+    }
+    for t in &set.timings {
+        let tuned = match t.tuned {
+            Some(v) if v < t.clocks => format!("tuned {} ({})", v, format!("-{}", t.clocks - v).yellow()),
+            Some(v) => format!("tuned {}", v),
+            None => String::new(),
+        };
+        println!("    {:<7} {:>8.3} ns  {:>4} clk  {:<13} {}", t.name, t.ns, t.clocks, t.source, tuned);
+    }
+}
+
+/// `timings <image> [--rate 6400,6933,7066] [--part K3LKBKB0BM]`
+pub fn run_timings(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let usage = "usage: bios_analyzer timings <image> [--rate 6400,6933,7066] [--part <part-number prefix>]";
+    let image = args.first().ok_or(usage)?;
+    let mut rates = DEFAULT_RATES.to_vec();
+    let mut part = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match (arg.as_str(), rest.next()) {
+            ("--rate", Some(list)) => {
+                rates = list.split(',')
+                    .map(|r| r.trim().parse::<u32>().map_err(|e| format!("bad rate '{}': {}", r, e)))
+                    .collect::<Result<_, _>>()?;
+                if rates.contains(&0) {
+                    return Err("rate must be non-zero".into());
+                }
+            }
+            ("--part", Some(prefix)) => part = Some(prefix.clone()),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    println!("{}", "═".repeat(80).cyan());
+    println!("{}", " LPDDR5 TIMING CALCULATOR".bold().cyan());
+    println!("{}", "═".repeat(80).cyan());
+
+    let file = File::open(image)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let data = &mmap[..];
+
+    // One SPD per distinct part/configuration
+    let mut seen = Vec::new();
+    for offset in find_pattern(data, SPD_SIGNATURE) {
+        let Some(info) = decode_spd(data, offset) else { continue };
+        if part.as_ref().is_some_and(|p| !info.part_number.starts_with(p.as_str())) {
+            continue;
+        }
+        let key = (info.part_number.clone(), spd_configuration(&info));
+        if seen.contains(&key) {
+            continue;
+        }
+        println!("\n  SPD @ 0x{:08X}: {} {}", offset,
+            info.manufacturer.as_deref().unwrap_or("unknown"), key.1);
+        for &rate in &rates {
+            print_timing_set(&calculate_timings(&info, rate));
+        }
+        seen.push(key);
+    }
+    if seen.is_empty() {
+        return Err("no matching LPDDR5 SPD found".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_image::*;
+
+    #[test]
+    fn to_clocks_guard_band() {
+        // 14.0 and 14.024 clocks stay 14 inside the 2.5% guard band, 14.027 rounds up
+        assert_eq!(to_clocks(17_500, 1250), 14);
+        assert_eq!(to_clocks(17_530, 1250), 14);
+        assert_eq!(to_clocks(17_534, 1250), 15);
+        assert_eq!(tck_ps_for_rate(6400), 1250);
+        assert_eq!(tck_ps_for_rate(7066), 1132);
+    }
+
+    #[test]
+    fn mgcp_timings_at_6400() {
+        let info = decode_spd(&mgcp_spd(), 0).unwrap();
+        let set = calculate_timings(&info, 6400);
+        assert_eq!((set.tck_ps, set.over_spec, set.cas_latency), (1250, false, Some(20)));
+        let clocks: Vec<(&str, u32, Option<u32>)> = set.timings.iter()
+            .map(|t| (t.name.as_str(), t.clocks, t.tuned))
+            .collect();
+        // JEDEC values next to the hand-tuned guide values of BIOS_SECRETS.md section 5
+        assert_eq!(clocks, [
+            ("tAA", 17, None),
+            ("tRCD", 15, Some(0x0C)),
+            ("tRPab", 17, None),
+            ("tRPpb", 15, Some(0x0B)),
+            ("tRAS", 34, Some(0x15)),
+            ("tRC", 51, Some(0x20)),
+            ("tRFCab", 224, None),
+            ("tRFCpb", 112, None),
+        ]);
+    }
+
+    #[test]
+    fn tras_uses_the_jedec_floor_at_every_rate() {
+        let info = decode_spd(&mgcp_spd(), 0).unwrap();
+        let set = calculate_timings(&info, 7066);
+        let tras = set.timings.iter().find(|t| t.name == "tRAS").unwrap();
+        assert_eq!((tras.ns, tras.clocks, tras.source.as_str(), tras.tuned), (42.0, 38, "JEDEC", None));
+        let trc = set.timings.iter().find(|t| t.name == "tRC").unwrap();
+        assert_eq!((trc.ns, trc.clocks), (63.0, 56));
+        assert!(set.over_spec);
+    }
+}
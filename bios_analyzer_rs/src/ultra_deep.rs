@@ -1,10 +1,12 @@
 //! Ultra deep analysis - H2O unlock, UMC, Fan curves, Thermal thresholds
 
+use crate::confidence::*;
+use crate::structures::*;
 use byteorder::{LittleEndian, ReadBytesExt};
 use colored::Colorize;
 use std::io::Cursor;
 
-pub fn ultra_deep_analysis(data: &[u8]) {
+pub fn ultra_deep_analysis(data: &[u8], scoring: &ScoringContext, power_limits: &[PowerLimit]) {
     println!("\n{}", "═".repeat(80).bright_yellow());
     println!("{}", " ULTRA DEEP ANALYSIS".bold().bright_yellow());
     println!("{}", "═".repeat(80).bright_yellow());
@@ -16,7 +18,7 @@ pub fn ultra_deep_analysis(data: &[u8]) {
     analyze_umc(data);
     
     // 3. All fan curves
-    analyze_all_fan_curves(data);
+    analyze_all_fan_curves(data, scoring);
     
     // 4. Thermal thresholds and throttling
     analyze_thermal_thresholds(data);
@@ -25,10 +27,10 @@ pub fn ultra_deep_analysis(data: &[u8]) {
     analyze_smu_messages(data);
     
     // 6. Power tables detailed
-    analyze_power_tables_detailed(data);
+    analyze_power_tables_detailed(power_limits);
     
     // 7. GPU P-States
-    analyze_gpu_pstates(data);
+    analyze_gpu_pstates(data, scoring);
     
     // 8. APCB/APOB structures
     analyze_apcb_apob(data);
@@ -144,7 +146,7 @@ fn analyze_umc(data: &[u8]) {
     }
 }
 
-fn analyze_all_fan_curves(data: &[u8]) {
+fn analyze_all_fan_curves(data: &[u8], scoring: &ScoringContext) {
     println!("\n{}", "  [FAN CURVES - DETAILED]".bold().bright_green());
     
     let mut fan_curves = Vec::new();
@@ -199,13 +201,21 @@ fn analyze_all_fan_curves(data: &[u8]) {
         }
     }
     
-    // Deduplicate and show
+    // Deduplicate, score and show the most likely first
     fan_curves.sort_by_key(|(o, _, _)| *o);
     fan_curves.dedup_by_key(|(o, _, _)| *o);
+    let mut fan_curves: Vec<_> = fan_curves.into_iter()
+        .map(|(o, temps, speeds)| {
+            let confidence = scoring.score(o, Topic::Fan, fan_curve_plausibility(&temps, &speeds));
+            (o, temps, speeds, confidence)
+        })
+        .filter(|(_, _, _, c)| scoring.passes(c))
+        .collect();
+    fan_curves.sort_by_key(|(_, _, _, c)| std::cmp::Reverse(c.score));
     
     println!("    Found {} potential fan curves:", fan_curves.len());
-    for (offset, temps, speeds) in fan_curves.iter().take(10) {
-        println!("\n    @ 0x{:08X}:", offset);
+    for (offset, temps, speeds, confidence) in fan_curves.iter().take(10) {
+        println!("\n    @ 0x{:08X}: [{}% {}: {}]", offset, confidence.score, confidence.region.name(), confidence.reasons.join(", "));
         println!("      Temps:  {:?}°C", temps);
         println!("      Speeds: {:?}%", speeds);
         
@@ -391,69 +401,25 @@ fn analyze_smu_messages(data: &[u8]) {
 }
 
 
-fn analyze_power_tables_detailed(data: &[u8]) {
+/// Power limits located through their PBS/CBS questions; bare 3-30 W values and runs
+/// of multiples of 500 are mostly coincidental, so they are not listed
+fn analyze_power_tables_detailed(power_limits: &[PowerLimit]) {
     println!("\n{}", "  [POWER TABLES - DETAILED]".bold().bright_green());
-    
-    // Power values in mW
-    let power_values: &[(u32, &str)] = &[
-        (3000, "3W - Ultra Low"),
-        (4000, "4W - Min TDP"),
-        (6000, "6W - Low"),
-        (8000, "8W - Eco"),
-        (10000, "10W - Medium Low"),
-        (12000, "12W - Medium"),
-        (15000, "15W - Default TDP"),
-        (18000, "18W - High"),
-        (20000, "20W - Performance"),
-        (25000, "25W - Boost"),
-        (28000, "28W - High Boost"),
-        (30000, "30W - Max"),
-    ];
-    
-    println!("    Power limit locations:");
-    for (mw, desc) in power_values {
-        let pattern = mw.to_le_bytes();
-        let matches = find_pattern_all(data, &pattern);
-        if !matches.is_empty() && matches.len() < 50 {
-            println!("      {} ({}mW): {} locations", desc.green(), mw, matches.len());
-            for &offset in matches.iter().take(4) {
-                // Show context
-                let ctx_start = offset.saturating_sub(8);
-                let ctx_end = (offset + 16).min(data.len());
-                let ctx = &data[ctx_start..ctx_end];
-                println!("        @ 0x{:08X}: {}", offset, 
-                    ctx.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "));
-            }
-        }
+
+    if power_limits.is_empty() {
+        println!("    No power-limit questions found; raw power values are not listed");
+        return;
     }
-    
-    // Look for power table structures
-    println!("\n    {}", "Power table structures:".yellow());
-    
-    // Pattern: multiple power values in sequence (STAPM, Fast PPT, Slow PPT, etc.)
-    for i in 0..data.len().saturating_sub(32) {
-        let mut cursor = Cursor::new(&data[i..i+32]);
-        let mut vals = Vec::new();
-        
-        for _ in 0..8 {
-            if let Ok(v) = cursor.read_u32::<LittleEndian>() {
-                vals.push(v);
-            }
-        }
-        
-        // Check if this looks like a power table
-        let power_vals: Vec<u32> = vals.iter()
-            .filter(|&&v| v >= 1000 && v <= 50000 && v % 500 == 0)
-            .copied()
-            .collect();
-        
-        if power_vals.len() >= 3 {
-            println!("      @ 0x{:08X}: {:?} mW", i, power_vals);
-        }
+    println!("    Power limit locations:");
+    for limit in power_limits {
+        let value = if limit.value == 0 { "Auto".to_string() } else { format!("{} {}", limit.value, limit.unit) };
+        println!("      {:<9} {:>10} @ 0x{:08X} ({}, '{}'{})", limit.name.green(), value, limit.offset,
+            limit.source, limit.prompt,
+            limit.varstore.as_ref().map_or(String::new(), |v| format!(", {}+0x{:04X}", v, limit.var_offset)));
     }
 }
 
-fn analyze_gpu_pstates(data: &[u8]) {
+fn analyze_gpu_pstates(data: &[u8], scoring: &ScoringContext) {
     println!("\n{}", "  [GPU P-STATES]".bold().bright_green());
     
     // GPU P-states typically contain: frequency, voltage pairs
@@ -485,14 +451,22 @@ fn analyze_gpu_pstates(data: &[u8]) {
             // Check if frequencies are ascending
             let freqs_ascending = entries.windows(2).all(|w| w[0].0 <= w[1].0);
             if freqs_ascending {
-                pstate_candidates.push((i, entries));
+                let confidence = scoring.score(i, Topic::Gpu, plausibility(&[
+                    (entries.windows(2).all(|w| w[0].1 <= w[1].1), 15, "voltage rises with frequency"),
+                    (entries.windows(2).all(|w| w[0].0 < w[1].0), 10, "distinct frequencies"),
+                    (entries.iter().all(|e| on_vid_grid(e.1 as f64)), 5, "voltages on the 6.25 mV VID grid"),
+                ]));
+                if scoring.passes(&confidence) {
+                    pstate_candidates.push((i, entries, confidence));
+                }
             }
         }
     }
     
+    pstate_candidates.sort_by_key(|(_, _, c)| std::cmp::Reverse(c.score));
     println!("    Found {} potential P-state tables:", pstate_candidates.len());
-    for (offset, entries) in pstate_candidates.iter().take(5) {
-        println!("\n      @ 0x{:08X}:", offset);
+    for (offset, entries, confidence) in pstate_candidates.iter().take(5) {
+        println!("\n      @ 0x{:08X}: [{}% {}: {}]", offset, confidence.score, confidence.region.name(), confidence.reasons.join(", "));
         for (j, (freq, volt)) in entries.iter().enumerate() {
             println!("        P{}: {} MHz @ {} mV", j, freq, volt);
         }
@@ -0,0 +1,108 @@
+//! Post-patch re-validation - structural tree and byte diff of input vs output
+
+use crate::apcb::*;
+use crate::checksums::*;
+use crate::ffs::*;
+use crate::nvram::*;
+use crate::psp::*;
+use colored::Colorize;
+use std::collections::BTreeMap;
+
+fn ok(valid: bool) -> &'static str {
+    if valid { "ok" } else { "BAD" }
+}
+
+/// Structural tree flattened to `path -> shape`. Shapes describe layout and
+/// checksum state, not contents, so intended value changes do not show up here.
+pub fn structure_snapshot(data: &[u8]) -> BTreeMap<String, String> {
+    let mut tree = BTreeMap::new();
+
+    if let Some(efs) = find_efs(data) {
+        let pointers: Vec<String> = efs.pointers.iter()
+            .map(|(name, raw, _)| format!("{}=0x{:08X}", name, raw))
+            .collect();
+        tree.insert(format!("efs@{:08X}", efs.offset), pointers.join(" "));
+    }
+
+    let volumes = parse_firmware_volumes(data);
+    for fv in all_volumes(&volumes) {
+        tree.insert(format!("fv@{:08X}", fv.offset), format!("len=0x{:X} fs={} files={} header_checksum={}",
+            fv.length, fv.guid, fv.files.len(), ok(fv.header_checksum_valid)));
+    }
+    for file in all_files(&volumes) {
+        tree.insert(format!("ffs@{:08X} {}", file.offset, file.guid), format!(
+            "size=0x{:X} type=0x{:02X} attr=0x{:02X} state=0x{:02X} sections={} checksums={}",
+            file.size, file.file_type, file.attributes, file.state,
+            flatten_sections(&file.sections).len(), ok(file_checksums_valid(data, file))));
+    }
+
+    for dir in parse_psp_directories(data) {
+        let entries: Vec<String> = dir.entries.iter()
+            .map(|e| format!("{:02X}:{:X}@{:X}", e.entry_type, e.size, e.location))
+            .collect();
+        tree.insert(format!("{}@{:08X}", dir.signature, dir.offset), format!("checksum={} [{}]",
+            ok(dir.checksum_valid), entries.join(" ")));
+    }
+
+    for apcb in parse_apcbs(data) {
+        let groups: Vec<String> = apcb.groups.iter()
+            .map(|g| format!("{}:{}", g.signature, g.entries.len()))
+            .collect();
+        let tokens: Vec<String> = apcb.tokens.iter().map(|t| format!("{:08X}", t.id)).collect();
+        tree.insert(format!("apcb@{:08X}", apcb.offset), format!("size=0x{:X} checksum={} groups=[{}] tokens=[{}]",
+            apcb.size, ok(apcb.checksum_valid), groups.join(" "), tokens.join(" ")));
+    }
+
+    for store in parse_variable_stores(data) {
+        tree.insert(format!("nvram@{:08X}", store.offset), format!("size=0x{:X} variables={} active={}",
+            store.size, store.variables.len(), store.variables.iter().filter(|v| v.is_active()).count()));
+        for var in &store.variables {
+            tree.insert(format!("nvram@{:08X} {} {}", var.offset, var.guid, var.name),
+                format!("attr=0x{:X} state=0x{:02X} size=0x{:X}", var.attributes, var.state, var.data_size));
+        }
+    }
+
+    tree
+}
+
+/// Re-parse `output` and compare it with `input`: only bytes inside `allowed`
+/// (offset, length) may differ, and every structure must keep its shape.
+pub fn validate_patched(input: &[u8], output: &[u8], allowed: &[(usize, usize)]) -> Result<(), String> {
+    let mut problems = Vec::new();
+
+    if input.len() != output.len() {
+        problems.push(format!("size changed: {} -> {}", input.len(), output.len()));
+    } else {
+        let stray: Vec<usize> = input.iter().zip(output).enumerate()
+            .filter(|(i, (a, b))| a != b && !allowed.iter().any(|&(o, l)| *i >= o && *i < o + l))
+            .map(|(i, _)| i)
+            .collect();
+        for offset in stray.iter().take(16) {
+            problems.push(format!("unexpected change @ 0x{:08X}: {:02X} -> {:02X}",
+                offset, input[*offset], output[*offset]));
+        }
+        if stray.len() > 16 {
+            problems.push(format!("... {} unexpected bytes in total", stray.len()));
+        }
+    }
+
+    let before = structure_snapshot(input);
+    let after = structure_snapshot(output);
+    for (path, shape) in &before {
+        match after.get(path) {
+            None => problems.push(format!("{} no longer parses", path)),
+            Some(new) if new != shape => problems.push(format!("{} changed: {} -> {}", path, shape, new)),
+            _ => {}
+        }
+    }
+    for path in after.keys().filter(|p| !before.contains_key(*p)) {
+        problems.push(format!("{} appeared", path));
+    }
+
+    println!("    {} structures compared, {} problem(s)", before.len(), problems.len());
+    if !problems.is_empty() {
+        return Err(format!("post-patch validation failed, DO NOT FLASH:\n  {}", problems.join("\n  ")));
+    }
+    println!("    {}", "output re-parsed, structure unchanged".green());
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Memory voltage indices - PBS/CBS voltage questions checked against documented safe ranges
+
+use crate::ifr_parser::*;
+use crate::structures::*;
+use colored::Colorize;
+use regex::Regex;
+
+/// A memory rail set through an index in the AMD PBS/CBS menus
+pub struct VoltageRail {
+    pub name: &'static str,
+    /// Prompt substrings (upper case) that identify the question
+    keywords: &'static [&'static str],
+    /// Documented safe index range, inclusive
+    pub safe: Option<(u64, u64)>,
+    source: &'static str,
+}
+
+/// BIOS_SECRETS.md section 5 and the overclocking guide. No rail has a published
+/// index-to-volt scale: VDDQ is a PHY index of the APU memory controller, and the
+/// VDD2H "92 = ~1.15 V" note contradicts the guide's 0-5 range, so only the guide
+/// range is used. VDD2L values disagree between sources (guide 0-2, chat 100), so
+/// it has no limit.
+const VOLTAGE_RAILS: &[VoltageRail] = &[
+    VoltageRail {
+        name: "VDDQ",
+        keywords: &["VDDQ"],
+        safe: Some((124, 144)),
+        source: "controller PHY index, guide range 124-144",
+    },
+    VoltageRail {
+        name: "VDD2H",
+        keywords: &["VDD2H", "VDDIO"],
+        safe: Some((0, 5)),
+        source: "no published scale, guide range 0-5",
+    },
+    VoltageRail {
+        name: "VDD2L",
+        keywords: &["VDD2L"],
+        safe: None,
+        source: "no documented scale",
+    },
+];
+
+/// Rail whose keyword appears in a question prompt
+pub fn voltage_rail(prompt: &str) -> Option<&'static VoltageRail> {
+    let prompt = prompt.to_ascii_uppercase();
+    VOLTAGE_RAILS.iter().find(|r| r.keywords.iter().any(|k| prompt.contains(k)))
+}
+
+/// Scale stated in a question's help text: "<base> V + <step> mV per step" or just "<step> mV per step"
+fn help_scale(help: &str) -> Option<(f64, f64)> {
+    let step = Regex::new(r"(?i)(\d+(?:\.\d+)?)\s*mV\s*(?:/|per)\s*(?:step|unit|index)").ok()?;
+    let base = Regex::new(r"(?i)(\d+(?:\.\d+)?)\s*V\s*\+").ok()?;
+    let step_mv = step.captures(help)?[1].parse().ok()?;
+    let base_mv = base.captures(help)
+        .and_then(|c| c[1].parse::<f64>().ok())
+        .map_or(0.0, |v| v * 1000.0);
+    Some((base_mv, step_mv))
+}
+
+/// Index to mV with the given scale
+fn index_mv(scale: Option<(f64, f64)>, index: u64) -> Option<f64> {
+    scale.map(|(base, step)| base + step * index as f64)
+}
+
+/// Warning when `index` is outside the documented safe range of `rail`
+pub fn voltage_warning(rail: &VoltageRail, index: u64) -> Option<String> {
+    let (min, max) = rail.safe?;
+    (index < min || index > max).then(|| format!("{} index {} is outside the documented safe range {}..{}",
+        rail.name, index, min, max))
+}
+
+/// Annotate every voltage-index question in the image's IFR
+pub fn analyze_voltage_settings(data: &[u8], report: &mut BiosReport) {
+    println!("{}", "Analyzing memory voltage indices...".dimmed());
+
+    for form_set in parse_form_sets(data) {
+        for q in &form_set.questions {
+            let Some(rail) = voltage_rail(&q.prompt) else { continue };
+            // Only the question's own help text can turn an index into volts
+            let scale = help_scale(&q.help);
+            let scale_source = if scale.is_some() { "IFR help text" } else { rail.source }.to_string();
+            let mut warnings: Vec<String> = q.default.iter()
+                .filter_map(|&d| voltage_warning(rail, d))
+                .map(|w| format!("default: {}", w))
+                .collect();
+            if let Some((_, max)) = rail.safe.filter(|&(_, max)| q.max > max && q.options.is_empty()) {
+                warnings.push(format!("menu allows up to {}, documented safe max is {}", q.max, max));
+            }
+            warnings.extend(q.options.iter()
+                .filter_map(|o| voltage_warning(rail, o.value).map(|w| format!("option '{}': {}", o.text, w))));
+            report.voltage_settings.push(VoltageSetting {
+                offset: q.offset as u64,
+                rail: rail.name.to_string(),
+                prompt: q.prompt.clone(),
+                varstore: form_set.varstores.iter().find(|v| v.id == q.varstore_id).map(|v| v.name.clone()),
+                var_offset: q.var_offset,
+                min: q.min,
+                max: q.max,
+                default: q.default,
+                default_mv: q.default.and_then(|d| index_mv(scale, d)),
+                min_mv: index_mv(scale, q.min),
+                max_mv: index_mv(scale, q.max),
+                safe: rail.safe,
+                scale_source,
+                warnings,
+            });
+        }
+    }
+
+    println!("  Found {} voltage index questions", report.voltage_settings.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn help_scale_is_the_only_volt_source() {
+        assert_eq!(help_scale("0.5 V + 5 mV per step"), Some((500.0, 5.0)));
+        assert_eq!(help_scale("10 mV/step"), Some((0.0, 10.0)));
+        assert_eq!(help_scale("VDDQ voltage index"), None);
+    }
+
+    #[test]
+    fn voltage_warning_checks_the_safe_range() {
+        let vddq = voltage_rail("Mem VDDQ Voltage").unwrap();
+        assert!(voltage_warning(vddq, 144).is_none());
+        assert!(voltage_warning(vddq, 150).unwrap().contains("124..144"));
+        assert!(voltage_warning(voltage_rail("VDD2L").unwrap(), 100).is_none());
+    }
+}